@@ -3,181 +3,159 @@
 // Instead this is more as a testing tool.
 // For production prover, see https://github.com/scroll-tech/scroll/tree/develop/prover
 
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand};
+use failure::{FailureReport, ProveError};
 use integration::{
+    batch_builder::{Batch, BatchBuilder, BatchLimits},
     capacity_checker::{
-        ccc_by_chunk, prepare_circuit_capacity_checker, run_circuit_capacity_checker, CCCMode,
+        ccc_by_chunk, compare_ccc_results, panic_message, prepare_circuit_capacity_checker,
+        run_circuit_capacity_checker, scan_blocks, CCCMode, CccDiscrepancy, CccTolerance,
+        ScanConfig, ScanError,
     },
-    l2geth,
+    ccc_report::{CccRecord, CccReport, Scope},
+    chunk_builder::{Chunk, ChunkBuilder, ChunkLimits},
+    chunk_info::chunk_info_from_block_traces,
+    constants::N_BLOB_BYTES,
+    fork_impact::{ForkImpactRecord, ForkImpactReport},
+    l2geth::{self, ClientPolicy, FetchConfig, TraceStream},
+    rollupscan,
+    simulation::SimulationStats,
+    trace_cache::TraceCache,
+    trace_file::{trace_path, write_trace_file, Compression},
+    trace_manifest::{TraceManifest, MANIFEST_FILE},
+    trace_request::{Fork, TraceRequest},
 };
+use journal::{ChunkContext, Journal, Status};
 use prover::{
     eth_types::l2_types::BlockTrace, init_env_and_log, BatchData, ChunkInfo, ChunkProofV2,
-    CircuitCapacityChecker, RowUsage, MAX_AGG_SNARKS,
+    CircuitCapacityChecker, MAX_AGG_SNARKS,
+};
+use std::{
+    collections::BTreeSet,
+    env,
+    fs::{self, File, OpenOptions},
+    io::BufWriter,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
 };
-use std::env;
 
-mod constants;
+mod failure;
+mod journal;
 mod prove_utils;
-mod rollupscan_client;
 
-fn warmup() {
-    prepare_circuit_capacity_checker();
-    log::info!("chain_prover: prepared ccc");
+/// Reports of a run, saved in the output dir.
+struct Reports {
+    failure: FailureReport,
+    ccc: Mutex<CccReport>,
+    ccc_path: PathBuf,
+    ccc_discrepancies: Mutex<Vec<CccDiscrepancy>>,
 }
 
-struct BatchBuilder {
-    chunks: Vec<ChunkInfo>,
-    batch_data: BatchData<{ MAX_AGG_SNARKS }>,
-}
+impl Reports {
+    fn new(output_dir: &str) -> Self {
+        let ccc_path = Path::new(output_dir).join("ccc_report");
+        // Records are appended to it during the run.
+        let _ = fs::remove_file(ccc_path.with_extension("jsonl"));
 
-impl BatchBuilder {
-    pub fn new() -> Self {
         Self {
-            chunks: Vec::new(),
-            batch_data: BatchData {
-                num_valid_chunks: 0,
-                chunk_sizes: [0u32; MAX_AGG_SNARKS],
-                chunk_data: std::iter::repeat_with(Vec::new)
-                    .take(MAX_AGG_SNARKS)
-                    .collect::<Vec<_>>()
-                    .try_into()
-                    .unwrap(),
-            },
-        }
-    }
-    fn reset(&mut self) {
-        self.chunks.clear();
-        self.batch_data = BatchData {
-            num_valid_chunks: 0,
-            chunk_sizes: [0u32; MAX_AGG_SNARKS],
-            chunk_data: std::iter::repeat_with(Vec::new)
-                .take(MAX_AGG_SNARKS)
-                .collect::<Vec<_>>()
-                .try_into()
-                .unwrap(),
-        };
+            failure: FailureReport::new(output_dir),
+            ccc: Mutex::new(CccReport::new()),
+            ccc_path,
+            ccc_discrepancies: Mutex::new(vec![]),
+        }
     }
-    fn add_chunk(&mut self, chunk: ChunkInfo) {
-        let idx = self.batch_data.num_valid_chunks as usize;
-        self.batch_data.chunk_sizes[idx] = chunk.tx_bytes.len() as u32;
-        self.batch_data.chunk_data[idx] = chunk.tx_bytes.clone();
-        self.batch_data.num_valid_chunks += 1;
-        self.chunks.push(chunk);
-    }
-    pub fn add(&mut self, chunk: ChunkInfo) -> Option<Vec<ChunkInfo>> {
-        self.add_chunk(chunk.clone());
-        log::debug!(
-            "BatchBuilder: checking chunk with len {}",
-            self.chunks.len()
-        );
 
-        // Condition0: chunk num
-        let condition0 = self.chunks.len() >= MAX_AGG_SNARKS;
-        if condition0 {
-            let batch = self.chunks.clone();
-            self.reset();
-            return Some(batch);
-        }
-
-        let batch_bytes = self.batch_data.get_batch_data_bytes();
-        let blob_bytes = prover::get_blob_bytes(&batch_bytes);
-        let compressed_da_size = blob_bytes.len();
-        let uncompressed_da_size = self
-            .batch_data
-            .chunk_sizes
-            .iter()
-            .map(|s| *s as u64)
-            .sum::<u64>();
-        let uncompressed_da_size_limit = BatchData::<{ MAX_AGG_SNARKS }>::n_rows_data() as u64;
-        // Condition1: compressed bytes size
-        let condition1 = compressed_da_size >= constants::N_BLOB_BYTES;
-        // Condition2: uncompressed bytes size
-        let condition2 = uncompressed_da_size > uncompressed_da_size_limit;
-
-        let overflow = condition1 || condition2;
-        if overflow {
-            // pop the last chunk and emit prev chunks
-            self.chunks.truncate(self.chunks.len() - 1);
-            let batch = self.chunks.clone();
-
-            self.reset();
-            self.add_chunk(chunk);
-
-            Some(batch)
-        } else {
-            None
+    /// Adds CCC records, and appends them to `ccc_report.jsonl`.
+    fn add_ccc(&self, report: CccReport, discrepancies: Vec<CccDiscrepancy>) {
+        self.ccc_discrepancies.lock().unwrap().extend(discrepancies);
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.ccc_path.with_extension("jsonl"))
+            .map_err(anyhow::Error::from)
+            .and_then(|file| report.write_jsonl(BufWriter::new(file)));
+        if let Err(e) = result {
+            log::error!("chain_prover: failed to save ccc report: {e}");
         }
+        self.ccc.lock().unwrap().extend(report);
     }
-}
 
-struct ChunkBuilder {
-    traces: Vec<BlockTrace>,
-    acc_row_usage_normalized: RowUsage,
-    block_limit: Option<usize>,
-}
-
-/// Same with production "chunk proposer"
-impl ChunkBuilder {
-    pub fn new() -> Self {
-        Self {
-            traces: Vec::new(),
-            acc_row_usage_normalized: RowUsage::default(),
-            block_limit: None,
-        }
-    }
-    pub fn add(&mut self, trace: BlockTrace) -> Option<Vec<BlockTrace>> {
-        // Condition1: block num
-        if let Some(block_limit) = self.block_limit {
-            if self.traces.len() + 1 == block_limit {
-                // build chunk
-                let mut chunk = self.traces.clone();
-                chunk.push(trace.clone());
-                self.traces.clear();
-                return Some(chunk);
+    /// Writes `ccc_report.csv`, and logs the summaries of all reports.
+    fn finish(&self) {
+        let ccc = self.ccc.lock().unwrap();
+        if !ccc.is_empty() {
+            let result = File::create(self.ccc_path.with_extension("csv"))
+                .map_err(anyhow::Error::from)
+                .and_then(|file| ccc.write_csv(BufWriter::new(file)));
+            if let Err(e) = result {
+                log::error!("chain_prover: failed to save ccc report: {e}");
             }
         }
+        self.log_ccc_summary();
+        self.failure.log_summary();
+    }
 
-        // Condition2: ccc
-        let ccc_result = {
-            let mut checker = CircuitCapacityChecker::new();
-            checker.estimate_circuit_capacity(trace.clone()).unwrap()
-        };
-        self.acc_row_usage_normalized.add(&ccc_result);
-        if !self.acc_row_usage_normalized.is_ok {
-            // build a chunk with PREV traces
-            let chunk = self.traces.clone();
-            self.traces.clear();
-            self.traces.push(trace);
-            self.acc_row_usage_normalized = ccc_result;
-            Some(chunk)
-        } else {
-            self.traces.push(trace);
-            None
+    /// Logs every block range where Signer or a follower mode under-estimated Optimal.
+    fn log_ccc_summary(&self) {
+        let discrepancies = self.ccc_discrepancies.lock().unwrap();
+        if discrepancies.is_empty() {
+            return;
+        }
+        log::warn!(
+            "ccc summary: {} under-estimated sub-circuits",
+            discrepancies.len()
+        );
+        for d in discrepancies.iter().filter(|d| d.mode != CCCMode::Optimal) {
+            log::warn!(
+                "ccc summary: block {} to {}: {:?} {} rows {} < optimal {} ({:?})",
+                d.begin_block,
+                d.end_block,
+                d.mode,
+                d.sub_circuit,
+                d.rows,
+                d.optimal_rows,
+                d.severity,
+            );
         }
     }
 }
 
+fn warmup() {
+    prepare_circuit_capacity_checker();
+    log::info!("chain_prover: prepared ccc");
+}
+
 // Construct chunk myself
-async fn prove_by_block(l2geth: &l2geth::Client, begin_block: i64, end_block: i64) {
-    let mut chunk_builder = ChunkBuilder::new();
-    //chunk_builder.block_limit = Some(1);
-    let mut batch_builder = BatchBuilder::new();
-    let (begin_block, end_block) = if begin_block == 0 && end_block == 0 {
-        // Blocks within last 24 hours
-        let block_num = 24 * 1200;
-        log::info!("use latest {block_num} blocks");
-        let latest_block = l2geth.get_block_number().await.unwrap();
-        (latest_block as i64 - block_num, latest_block as i64)
-    } else {
-        (begin_block, end_block)
-    };
-    let mut batch_begin_block = begin_block;
-    for block_num in begin_block..=end_block {
-        let trace = l2geth
-        .get_block_trace_by_num(block_num, false)
-        .await
-        .unwrap_or_else(|e| {
-            panic!("chain_prover: failed to request l2geth block-trace API for block-{block_num}: {e}")
-        });
+async fn prove_by_block(
+    l2geth: &l2geth::Client,
+    reports: &Reports,
+    fetch: &FetchConfig,
+    chunk_limits: ChunkLimits,
+    batch_limits: BatchLimits,
+    begin_block: i64,
+    end_block: i64,
+) {
+    let mut chunk_builder = ChunkBuilder::new(chunk_limits);
+    let mut batch_builder = BatchBuilder::new(batch_limits);
+    let mut traces = l2geth.fetch_range(begin_block, end_block, fetch);
+    while let Some((block_num, result)) = traces.next().await {
+        let trace = match result {
+            Ok(trace) => trace,
+            Err(e) => {
+                // Chunks can't be built across a missing block, so stop here.
+                let err = ProveError::Fetch(format!(
+                    "failed to request l2geth block-trace API for block-{block_num}: {e}"
+                ));
+                reports
+                    .failure
+                    .record_blocks(None, None, block_num, end_block, &err);
+                break;
+            }
+        };
         log::info!(
             "fetch trace done. begin {} end {} cur {}, progress {:.1}%",
             begin_block,
@@ -185,34 +163,161 @@ async fn prove_by_block(l2geth: &l2geth::Client, begin_block: i64, end_block: i6
             block_num,
             100.0 * (block_num - begin_block + 1) as f32 / (end_block - begin_block + 1) as f32
         );
-        if let Some(chunk) = chunk_builder.add(trace) {
-            prove_chunk(0, chunk[0].header.number.unwrap().as_u64(), chunk.clone());
-            let fast = false;
-            let chunk_info = if fast {
-                unimplemented!("uncomment below");
-                //ChunkInfo::from_block_traces(&chunk)
-            } else {
-                let witness_block = prover::chunk_trace_to_witness_block(chunk).unwrap();
-                ChunkInfo::from_witness_block(&witness_block, false)
-            };
-            if let Some(batch) = batch_builder.add(chunk_info) {
-                let mut padded_batch = batch.clone();
-                padding_chunk(&mut padded_batch);
-                let batch_data = BatchData::<{ MAX_AGG_SNARKS }>::new(batch.len(), &padded_batch);
-                let compressed_da_size =
-                    prover::get_blob_bytes(&batch_data.get_batch_data_bytes()).len();
-                log::info!(
-                    "batch built: blob usage {:.3}, chunk num {}, block num {}, block range {} to {}",
-                    compressed_da_size as f32 / constants::N_BLOB_BYTES as f32,
-                    batch.len(),
-                    block_num - batch_begin_block + 1,
-                    batch_begin_block,
-                    block_num,
-                );
-                batch_begin_block = block_num + 1;
+        match chunk_builder.add(trace) {
+            Ok(Some(chunk)) => {
+                for batch in prove_and_add_chunk(reports, &mut batch_builder, chunk) {
+                    log_batch(&batch);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                // Like a missing block, chunks can't be built across it.
+                let err = ProveError::WitnessGeneration(format!("{e:#}"));
+                reports
+                    .failure
+                    .record_blocks(None, None, block_num, end_block, &err);
+                break;
+            }
+        }
+    }
+
+    // Drain the blocks and chunks left at the end of the range.
+    if let Some(chunk) = chunk_builder.finish() {
+        for batch in prove_and_add_chunk(reports, &mut batch_builder, chunk) {
+            log_batch(&batch);
+        }
+    }
+    if let Some(batch) = batch_builder.finish() {
+        log_batch(&batch);
+    }
+}
+
+/// Proves a chunk built by `prove_by_block`, and adds it to the batch being built. Returns the
+/// batches closed by it.
+fn prove_and_add_chunk(
+    reports: &Reports,
+    batch_builder: &mut BatchBuilder,
+    chunk: Chunk,
+) -> Vec<Batch> {
+    log::info!(
+        "chunk built: {} blocks, {} txs, closed by {:?}",
+        chunk.stats.blocks,
+        chunk.stats.txs,
+        chunk.closed_by
+    );
+    let chunk_id = chunk.stats.first_block;
+    prove_chunk(reports, None, chunk_id, chunk.blocks.clone());
+    let chunk_info = chunk_info_from_block_traces(&chunk.blocks);
+    batch_builder
+        .add(chunk_info, &chunk.stats)
+        .unwrap_or_else(|e| {
+            let err = ProveError::BlobOverflow(e.to_string());
+            reports
+                .failure
+                .record_chunk(None, chunk_id, &chunk.blocks, &err);
+            vec![]
+        })
+}
+
+fn log_batch(batch: &Batch) {
+    log::info!("batch closed by {:?}", batch.closed_by);
+    let mut padded_batch = batch.chunks.clone();
+    padding_chunk(&mut padded_batch);
+    let batch_data = BatchData::<{ MAX_AGG_SNARKS }>::new(batch.chunks.len(), &padded_batch);
+    let compressed_da_size = prover::get_blob_bytes(&batch_data.get_batch_data_bytes()).len();
+    log::info!(
+        "batch built: blob usage {:.3}, chunk num {}, block num {}, block range {} to {}",
+        compressed_da_size as f32 / N_BLOB_BYTES as f32,
+        batch.chunks.len(),
+        batch.stats.blocks,
+        batch.stats.first_block,
+        batch.stats.last_block,
+    );
+}
+
+/// Runs the chunk and batch proposers over a block range without proving, and logs the
+/// utilization stats of the built chunks and batches.
+async fn simulate(
+    l2geth: &l2geth::Client,
+    reports: &Reports,
+    fetch: &FetchConfig,
+    chunk_limits: ChunkLimits,
+    batch_limits: BatchLimits,
+    begin_block: i64,
+    end_block: i64,
+) {
+    let mut chunk_builder = ChunkBuilder::new(chunk_limits);
+    let mut batch_builder = BatchBuilder::new(batch_limits);
+    let mut stats = SimulationStats::new();
+    let mut add_chunk = |chunk: Chunk| {
+        stats.add_chunk(&chunk);
+        let chunk_info = chunk_info_from_block_traces(&chunk.blocks);
+        match batch_builder.add(chunk_info, &chunk.stats) {
+            Ok(batches) => {
+                for batch in batches {
+                    log_simulated_batch(&batch);
+                    stats.add_batch(&batch);
+                }
+            }
+            Err(e) => {
+                let err = ProveError::BlobOverflow(e.to_string());
+                reports
+                    .failure
+                    .record_chunk(None, chunk.stats.first_block, &chunk.blocks, &err);
+            }
+        }
+    };
+    let mut traces = l2geth.fetch_range(begin_block, end_block, fetch);
+    while let Some((block_num, result)) = traces.next().await {
+        let trace = match result {
+            Ok(trace) => trace,
+            Err(e) => {
+                // Chunks can't be built across a missing block, so stop here.
+                let err = ProveError::Fetch(format!(
+                    "failed to request l2geth block-trace API for block-{block_num}: {e}"
+                ));
+                reports
+                    .failure
+                    .record_blocks(None, None, block_num, end_block, &err);
+                break;
+            }
+        };
+        match chunk_builder.add(trace) {
+            Ok(Some(chunk)) => add_chunk(chunk),
+            Ok(None) => {}
+            Err(e) => {
+                // Like a missing block, chunks can't be built across it.
+                let err = ProveError::WitnessGeneration(format!("{e:#}"));
+                reports
+                    .failure
+                    .record_blocks(None, None, block_num, end_block, &err);
+                break;
             }
         }
     }
+
+    // Drain the blocks and chunks left at the end of the range.
+    if let Some(chunk) = chunk_builder.finish() {
+        add_chunk(chunk);
+    }
+    if let Some(batch) = batch_builder.finish() {
+        log_simulated_batch(&batch);
+        stats.add_batch(&batch);
+    }
+
+    stats.log_summary();
+}
+
+fn log_simulated_batch(batch: &Batch) {
+    log::info!(
+        "simulate: batch built: {} chunks, {} blocks, block range {} to {}, blob usage {:.3}, closed by {:?}",
+        batch.stats.chunks,
+        batch.stats.blocks,
+        batch.stats.first_block,
+        batch.stats.last_block,
+        batch.stats.blob_utilization(),
+        batch.closed_by,
+    );
 }
 
 fn padding_chunk(chunks: &mut Vec<ChunkInfo>) {
@@ -233,10 +338,11 @@ fn padding_chunk(chunks: &mut Vec<ChunkInfo>) {
 }
 
 fn prove_chunk(
-    batch_id: u64,
+    reports: &Reports,
+    batch_id: Option<i64>,
     chunk_id: u64,
     block_traces: Vec<BlockTrace>,
-) -> Option<ChunkProofV2> {
+) -> (Status, Option<ChunkProofV2>) {
     let total_gas: u64 = block_traces
         .iter()
         .map(|b| b.header.gas_used.as_u64())
@@ -248,125 +354,483 @@ fn prove_chunk(
     );
 
     if env::var("CIRCUIT").unwrap_or_default() == "none" {
-        return None;
+        return (Status::Skipped, None);
     }
     if env::var("CIRCUIT").unwrap_or_default() == "ccc" {
         let ccc_modes = [CCCMode::Optimal];
-        run_circuit_capacity_checker(batch_id, chunk_id, &block_traces, &ccc_modes);
-        return None;
+        // Only one mode, nothing to compare.
+        let (ccc_report, discrepancies) = run_circuit_capacity_checker(
+            batch_id.unwrap_or_default() as u64,
+            chunk_id,
+            &block_traces,
+            &ccc_modes,
+            &CccTolerance::default(),
+        );
+        reports.add_ccc(ccc_report, discrepancies);
+        return (Status::CccOnly, None);
     }
 
-    let chunk_proof = prove_utils::prove_chunk(
-        &format!("chain_prover: batch-{batch_id} chunk-{chunk_id}"),
-        block_traces,
+    let result = prove_utils::prove_chunk(
+        &format!(
+            "chain_prover: batch-{} chunk-{chunk_id}",
+            batch_id.unwrap_or_default()
+        ),
+        block_traces.clone(),
     );
     log::info!("proving chunk done");
-    chunk_proof
+    match result {
+        Ok(chunk_proof) => (Status::Proved, chunk_proof),
+        Err(err) => {
+            reports
+                .failure
+                .record_chunk(batch_id, chunk_id, &block_traces, &err);
+            (Status::Failed, None)
+        }
+    }
 }
 
 // Use constructed chunk/batch info from coordinator
 async fn prove_by_batch(
     l2geth: &l2geth::Client,
-    rollupscan: &rollupscan_client::Client,
-    begin_batch: i64,
-    end_batch: i64,
+    rollupscan: &rollupscan::Client,
+    reports: &Reports,
+    fetch: &FetchConfig,
+    range: &BatchRange,
+    #[cfg(feature = "batch-prove")] mut prove_batch: impl FnMut(
+        i64,
+        Vec<ChunkProofV2>,
+        prover::BatchHeader<MAX_AGG_SNARKS>,
+    ) -> Result<(), ProveError>,
 ) {
-    for batch_id in begin_batch..=end_batch {
-        let chunks = rollupscan
-                .get_chunk_info_by_batch_index(batch_id)
-                .await
-                .unwrap_or_else(|e| {
-                    panic!("chain_prover: failed to request rollupscan chunks API for batch-{batch_id}: {e}")
-                });
+    let mut journal = Journal::open(&range.journal, range.resume).unwrap_or_else(|e| {
+        panic!(
+            "chain_prover: failed to open journal {}: {e}",
+            range.journal
+        )
+    });
 
-        if chunks.is_none() {
-            log::warn!("chain_prover: no chunks in batch-{batch_id}");
+    for batch_id in range.begin_batch..=range.end_batch {
+        if let Some(entry) = journal.batch(batch_id).filter(|e| e.status.is_done()) {
+            log::info!(
+                "chain_prover: skip batch-{batch_id}, {:?} in journal",
+                entry.status
+            );
             continue;
         }
+        let batch_start = Instant::now();
+
+        let chunks = match rollupscan.get_chunk_info_by_batch_index(batch_id).await {
+            Ok(Some(chunks)) if !chunks.is_empty() => chunks,
+            Ok(_) => {
+                log::warn!("chain_prover: no chunks in batch-{batch_id}");
+                continue;
+            }
+            Err(e) => {
+                let err = ProveError::Fetch(format!(
+                    "failed to request rollupscan chunks API for batch-{batch_id}: {e}"
+                ));
+                reports.failure.record_batch(batch_id, &err);
+                journal
+                    .record_batch(batch_id, Status::Failed, batch_start.elapsed())
+                    .unwrap_or_else(|e| panic!("chain_prover: failed to write journal: {e}"));
+                continue;
+            }
+        };
+        // Chunks finished in a previous run are neither fetched nor proved again. The batch
+        // header needs their context, which older journals may not have recorded.
+        let resumed = chunks
+            .iter()
+            .map(|chunk| {
+                journal
+                    .chunk(batch_id, chunk.index as u64)
+                    .filter(|e| e.status.is_done())
+                    .filter(|e| !cfg!(feature = "batch-prove") || e.context.is_some())
+                    .cloned()
+            })
+            .collect::<Vec<_>>();
+        #[cfg(feature = "batch-prove")]
+        let chunk_num = chunks.len();
+        #[cfg(feature = "batch-prove")]
+        let mut batch_ctx = BatchContext::default();
 
+        let mut traces = None;
         let mut chunk_proofs = vec![];
-        for chunk in chunks.unwrap() {
+        let mut all_chunks_done = true;
+        for (idx, chunk) in chunks.iter().enumerate() {
             let chunk_id = chunk.index as u64;
             log::info!("chain_prover: handling chunk {:?}", chunk_id);
 
-            let mut block_traces: Vec<BlockTrace> = vec![];
-            for block_num in chunk.start_block_number..=chunk.end_block_number {
-                let trace = l2geth
-                        .get_block_trace_by_num(block_num, false)
-                        .await
-                        .unwrap_or_else(|e| {
-                            panic!("chain_prover: failed to request l2geth block-trace API for batch-{batch_id} chunk-{chunk_id} block-{block_num}: {e}")
-                        });
-
-                block_traces.push(trace);
+            if let Some(entry) = &resumed[idx] {
+                log::info!(
+                    "chain_prover: skip chunk-{chunk_id}, {:?} in journal",
+                    entry.status
+                );
+                let chunk_proof = Journal::load_chunk_proof(entry).unwrap_or_else(|e| {
+                    panic!("chain_prover: failed to resume chunk-{chunk_id}: {e}")
+                });
+                #[cfg(feature = "batch-prove")]
+                batch_ctx.add_chunk(&entry.context.unwrap_or_default());
+                chunk_proofs.extend(chunk_proof);
+                // The stream ends before a resumed chunk.
+                traces = None;
+                continue;
             }
+            // Traces of the next chunks, up to the next resumed one, are fetched while proving
+            // a chunk.
+            let traces = traces.get_or_insert_with(|| {
+                let end_block = chunks[idx..]
+                    .iter()
+                    .zip(&resumed[idx..])
+                    .take_while(|(_, entry)| entry.is_none())
+                    .last()
+                    .map_or(chunk.end_block_number, |(chunk, _)| chunk.end_block_number);
+                l2geth.fetch_range(chunk.start_block_number, end_block, fetch)
+            });
 
-            let chunk_proof = prove_chunk(batch_id as u64, chunk_id, block_traces);
+            let block_traces =
+                match next_block_traces(traces, chunk.start_block_number, chunk.end_block_number)
+                    .await
+                {
+                    Ok(block_traces) => block_traces,
+                    Err(err) => {
+                        reports.failure.record_blocks(
+                            Some(batch_id),
+                            Some(chunk_id),
+                            chunk.start_block_number,
+                            chunk.end_block_number,
+                            &err,
+                        );
+                        journal
+                            .record_chunk(
+                                batch_id,
+                                chunk_id,
+                                Status::Failed,
+                                None,
+                                None,
+                                Duration::ZERO,
+                            )
+                            .unwrap_or_else(|e| {
+                                panic!("chain_prover: failed to write journal: {e}")
+                            });
+                        all_chunks_done = false;
+                        continue;
+                    }
+                };
+            let context = ChunkContext::from_traces(&block_traces);
+            #[cfg(feature = "batch-prove")]
+            batch_ctx.add_chunk(&context);
 
-            if let Some(chunk_proof) = chunk_proof {
-                chunk_proofs.push(chunk_proof);
-            }
+            let chunk_start = Instant::now();
+            let (status, chunk_proof) =
+                prove_chunk(reports, Some(batch_id), chunk_id, block_traces);
+            journal
+                .record_chunk(
+                    batch_id,
+                    chunk_id,
+                    status,
+                    chunk_proof.as_ref(),
+                    Some(context),
+                    chunk_start.elapsed(),
+                )
+                .unwrap_or_else(|e| panic!("chain_prover: failed to write journal: {e}"));
+
+            all_chunks_done &= status.is_done();
+            chunk_proofs.extend(chunk_proof);
         }
 
+        #[cfg(not(feature = "batch-prove"))]
+        let batch_status = if all_chunks_done {
+            Status::Skipped
+        } else {
+            Status::Failed
+        };
         #[cfg(feature = "batch-prove")]
-        use prover::BatchHeader;
-        #[cfg(feature = "batch-prove")]
-        let batch_header = BatchHeader::<MAX_AGG_SNARKS>::default();
-        #[cfg(feature = "batch-prove")]
-        prove_utils::prove_batch(
-            &format!("chain_prover: batch-{batch_id}"),
-            chunk_proofs,
-            batch_header,
-        );
+        let batch_status = if !all_chunks_done || chunk_proofs.len() != chunk_num {
+            log::error!(
+                "chain_prover: only {} of {chunk_num} chunks of batch-{batch_id} are proved, skip batch proving",
+                chunk_proofs.len()
+            );
+            Status::Failed
+        } else {
+            let result = build_batch_header(
+                rollupscan,
+                batch_id,
+                range.batch_version,
+                &batch_ctx,
+                &chunk_proofs,
+            )
+            .await
+            .map_err(|e| {
+                ProveError::Fetch(format!(
+                    "failed to build batch header of batch-{batch_id}: {e}"
+                ))
+            })
+            .and_then(|(batch_header, batch_hash)| {
+                if batch_header.batch_hash() != batch_hash {
+                    return Err(ProveError::Verify(format!(
+                        "batch hash {:?} of batch-{batch_id} mismatches the on-chain {batch_hash:?}",
+                        batch_header.batch_hash()
+                    )));
+                }
+                prove_batch(batch_id, chunk_proofs, batch_header)
+            });
+            match result {
+                Ok(()) => Status::Proved,
+                Err(err) => {
+                    reports.failure.record_batch(batch_id, &err);
+                    Status::Failed
+                }
+            }
+        };
+        journal
+            .record_batch(batch_id, batch_status, batch_start.elapsed())
+            .unwrap_or_else(|e| panic!("chain_prover: failed to write journal: {e}"));
     }
 }
 
-async fn txtx_ccc(l2geth: &l2geth::Client, begin_block: i64, end_block: i64) {
-    let (begin_block, end_block) = if begin_block == 0 && end_block == 0 {
-        // Blocks within last 24 hours
-        let block_num = 24 * 1200;
-        log::info!("use latest {block_num} blocks");
-        let latest_block = l2geth.get_block_number().await.unwrap();
-        (latest_block as i64 - block_num, latest_block as i64)
-    } else {
-        (begin_block, end_block)
+/// Takes the traces of `begin_block..=end_block` from `traces`, skipping the blocks before
+/// `begin_block`. All the blocks are taken even when some of them failed, so the stream is
+/// left at the next chunk.
+async fn next_block_traces(
+    traces: &mut TraceStream,
+    begin_block: i64,
+    end_block: i64,
+) -> Result<Vec<BlockTrace>, ProveError> {
+    let mut block_traces = vec![];
+    let mut first_err = None;
+    for expected in begin_block..=end_block {
+        let result = loop {
+            match traces.next().await {
+                Some((block_num, _)) if block_num < expected => continue,
+                Some((_, result)) => break result,
+                None => break Err(anyhow::anyhow!("trace is not fetched")),
+            }
+        };
+        match result {
+            Ok(trace) => block_traces.push(trace),
+            Err(e) => {
+                first_err.get_or_insert_with(|| {
+                    ProveError::Fetch(format!(
+                        "failed to request l2geth block-trace API for block-{expected}: {e}"
+                    ))
+                });
+            }
+        }
+    }
+    match first_err {
+        Some(err) => Err(err),
+        None => Ok(block_traces),
+    }
+}
+
+// Prove batches like `prove_by_batch`, and aggregate consecutive batch proofs into bundles.
+// Batches skipped on `--resume` break the consecutiveness, so bundles restart after them.
+// Without a `bundle_size`, batch proofs are not bundled.
+#[cfg(feature = "batch-prove")]
+#[allow(clippy::too_many_arguments)]
+async fn prove_by_bundle(
+    l2geth: &l2geth::Client,
+    rollupscan: &rollupscan::Client,
+    reports: &Reports,
+    fetch: &FetchConfig,
+    range: &BatchRange,
+    bundle_size: Option<usize>,
+    params_path: &str,
+    output_dir: &str,
+) {
+    use itertools::Itertools;
+    use prover::BATCH_PROVER_DEGREES;
+
+    let params_map = prover::Prover::load_params_map(
+        params_path,
+        &BATCH_PROVER_DEGREES.iter().copied().collect_vec(),
+    );
+    let mut bundle_prover =
+        prove_utils::BundleProver::new(&params_map, &reports.failure, output_dir, bundle_size);
+    prove_by_batch(
+        l2geth,
+        rollupscan,
+        reports,
+        fetch,
+        range,
+        |batch_id, chunk_proofs, batch_header| {
+            bundle_prover.add_batch(batch_id, chunk_proofs, batch_header)
+        },
+    )
+    .await;
+    bundle_prover.finish();
+}
+
+/// Batch header fields which are collected from block traces.
+#[cfg(feature = "batch-prove")]
+#[derive(Debug, Default)]
+struct BatchContext {
+    start_l1_queue_index: Option<u64>,
+    l1_message_popped: u64,
+    last_block_timestamp: u64,
+}
+
+#[cfg(feature = "batch-prove")]
+impl BatchContext {
+    fn add_chunk(&mut self, context: &ChunkContext) {
+        self.start_l1_queue_index
+            .get_or_insert(context.start_l1_queue_index);
+        self.l1_message_popped += context.l1_message_popped;
+        self.last_block_timestamp = context.last_block_timestamp;
+    }
+}
+
+/// Build the batch header the same way the rollup contract does, using the batch metadata from
+/// rollupscan and falling back to values collected from traces. Also returns the on-chain batch
+/// hash, which the header is expected to hash to.
+#[cfg(feature = "batch-prove")]
+async fn build_batch_header(
+    rollupscan: &rollupscan::Client,
+    batch_id: i64,
+    default_version: u8,
+    batch_ctx: &BatchContext,
+    chunk_proofs: &[ChunkProofV2],
+) -> anyhow::Result<(prover::BatchHeader<MAX_AGG_SNARKS>, prover::eth_types::H256)> {
+    use anyhow::anyhow;
+    use integration::prove::get_blob_from_chunks;
+    use itertools::Itertools;
+    use prover::BatchHeader;
+
+    let batch_info = rollupscan
+        .get_batch_info_by_index(batch_id)
+        .await?
+        .ok_or_else(|| anyhow!("batch-{batch_id} not found in rollupscan"))?;
+    let parent_batch_hash = match batch_info.parent_hash {
+        Some(parent_hash) => parent_hash,
+        None => {
+            rollupscan
+                .get_batch_info_by_index(batch_id - 1)
+                .await?
+                .ok_or_else(|| anyhow!("parent of batch-{batch_id} not found in rollupscan"))?
+                .hash
+        }
     };
+    // Skipped L1 messages are only known by rollupscan, traces count the included ones.
+    let l1_message_popped = batch_info
+        .l1_messages_popped
+        .unwrap_or(batch_ctx.l1_message_popped);
+    let total_l1_message_popped = batch_info
+        .total_l1_messages_popped
+        .unwrap_or(batch_ctx.start_l1_queue_index.unwrap_or_default() + l1_message_popped);
+
+    let chunk_infos = chunk_proofs
+        .iter()
+        .map(|p| p.inner.chunk_info().clone())
+        .collect_vec();
+    let blob_bytes = get_blob_from_chunks(&chunk_infos);
+    let batch_header = BatchHeader::construct_from_chunks(
+        batch_info.version.unwrap_or(default_version),
+        batch_id as u64,
+        l1_message_popped,
+        total_l1_message_popped,
+        parent_batch_hash,
+        batch_ctx.last_block_timestamp,
+        &chunk_infos,
+        &blob_bytes,
+    );
+    log::info!(
+        "chain_prover: batch-{batch_id} header built, hash {:?}, on-chain hash {:?}",
+        batch_header.batch_hash(),
+        batch_info.hash,
+    );
+
+    Ok((batch_header, batch_info.hash))
+}
+
+async fn txtx_ccc(
+    l2geth: &l2geth::Client,
+    reports: &Reports,
+    tolerance: &CccTolerance,
+    begin_block: i64,
+    end_block: i64,
+) {
     for block_num in begin_block..=end_block {
         // part1: real row usage
         let block_num = block_num as u64;
         let batch_id = block_num;
         let chunk_id = block_num;
-        let trace = l2geth
-        .get_block_trace_by_num(block_num as i64, false)
-        .await
-        .unwrap_or_else(|e| {
-            panic!("chain_prover: failed to request l2geth block-trace API for block-{block_num}: {e}")
-        });
-        let tx_traces = l2geth
-        .get_txbytx_trace_by_num(block_num as i64)
-        .await
-        .unwrap_or_else(|e| {
-            panic!("chain_prover: failed to request l2geth block-trace API for block-{block_num}: {e}")
-        });
-        let (real_usage, t) = ccc_by_chunk(batch_id, chunk_id, &[trace]);
+        let fetched = tokio::try_join!(
+            l2geth.get_block_trace_by_num(block_num as i64),
+            l2geth.get_txbytx_trace_by_num(block_num as i64),
+        );
+        let (trace, tx_traces) = match fetched {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                let err = ProveError::Fetch(format!(
+                    "failed to request l2geth block-trace API for block-{block_num}: {e}"
+                ));
+                reports
+                    .failure
+                    .record_blocks(None, None, block_num as i64, block_num as i64, &err);
+                continue;
+            }
+        };
+        let block_start_time = Instant::now();
+        let (real_usage, t) = ccc_by_chunk(batch_id, chunk_id, std::slice::from_ref(&trace));
+        let block_elapsed = block_start_time.elapsed();
 
         // part2: tx by tx row usage
         let tx_num = tx_traces.len();
         let mut checker = CircuitCapacityChecker::new();
         let start_time = std::time::Instant::now();
-        for tx in tx_traces {
-            checker.estimate_circuit_capacity(tx).unwrap();
+        let estimated = tx_traces.into_iter().enumerate().try_for_each(|(i, tx)| {
+            catch_unwind(AssertUnwindSafe(|| checker.estimate_circuit_capacity(tx)))
+                .map_err(|e| format!("ccc panicked: {}", panic_message(e)))
+                .and_then(|result| result.map(|_| ()).map_err(|e| e.to_string()))
+                .map_err(|e| format!("tx-by-tx ccc of tx {i} of block-{block_num} failed: {e}"))
+        });
+        if let Err(e) = estimated {
+            let err = ProveError::WitnessGeneration(e);
+            reports
+                .failure
+                .record_blocks(None, None, block_num as i64, block_num as i64, &err);
+            continue;
         }
         let row_usage = checker.get_acc_row_usage(false);
-        let avg_ccc_time = start_time.elapsed().as_millis() / tx_num as u128;
+        let txtx_elapsed = start_time.elapsed();
+        let avg_ccc_time = txtx_elapsed.as_millis() / tx_num.max(1) as u128;
+
+        let mut ccc_report = CccReport::new();
+        let blocks = std::slice::from_ref(&trace);
+        ccc_report.push(CccRecord::new(
+            Scope::Block,
+            CCCMode::Optimal,
+            batch_id,
+            chunk_id,
+            blocks,
+            &real_usage,
+            block_elapsed,
+        ));
+        ccc_report.push(CccRecord::new(
+            Scope::Block,
+            CCCMode::Siger,
+            batch_id,
+            chunk_id,
+            blocks,
+            &row_usage,
+            txtx_elapsed,
+        ));
+        let discrepancies = compare_ccc_results(
+            chunk_id,
+            blocks,
+            &real_usage,
+            CCCMode::Optimal,
+            &row_usage,
+            CCCMode::Siger,
+            tolerance,
+        );
+        reports.add_ccc(ccc_report, discrepancies);
 
         // part3: pretty print
         log::info!("circuit\ttxbytx\tblock\tblock-{block_num}");
         for i in 0..real_usage.row_usage_details.len() {
             let r1 = row_usage.row_usage_details[i].row_number;
             let r2 = real_usage.row_usage_details[i].row_number;
-            // FIXME: the "1" of bytecode circuit
-            assert!(r1 + 1 >= r2);
             let show_name: String = row_usage.row_usage_details[i]
                 .name
                 .chars()
@@ -378,84 +842,854 @@ async fn txtx_ccc(l2geth: &l2geth::Client, begin_block: i64, end_block: i64) {
     }
 }
 
+async fn ccc_scan(
+    l2geth: &l2geth::Client,
+    reports: &Reports,
+    config: &ScanConfig,
+    begin_block: i64,
+    end_block: i64,
+) {
+    scan_blocks(
+        begin_block as u64,
+        end_block as u64,
+        config,
+        |block_num| l2geth.get_block_trace_by_num(block_num as i64),
+        |block_num, result| match result {
+            Ok(result) => reports.add_ccc(result.report, result.discrepancies),
+            Err(e) => {
+                let err = match e {
+                    ScanError::Fetch(e) => ProveError::Fetch(format!(
+                        "failed to request l2geth block-trace API for block-{block_num}: {e}"
+                    )),
+                    ScanError::Check(e) => ProveError::WitnessGeneration(format!(
+                        "ccc of block-{block_num} failed: {e}"
+                    )),
+                };
+                reports
+                    .failure
+                    .record_blocks(None, None, block_num as i64, block_num as i64, &err);
+            }
+        },
+    )
+    .await;
+}
+
+async fn fork_impact(
+    l2geth: &l2geth::Client,
+    reports: &Reports,
+    args: &ForkImpactArgs,
+    output_dir: &str,
+    begin_block: i64,
+    end_block: i64,
+) {
+    let mut fork_report = ForkImpactReport::default();
+    for block_num in begin_block..=end_block {
+        let mut request = TraceRequest::by_number(block_num as u64);
+        if let Some(fork) = args.fork {
+            request = request.enable_fork(fork);
+        }
+        for o in &args.overrides {
+            request = request.override_config(&o.key, o.value.clone());
+        }
+        let fetched = tokio::try_join!(
+            l2geth.get_block_trace_by_num(block_num),
+            l2geth.get_block_trace(&request),
+        );
+        let (native, overridden) = match fetched {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                let err = ProveError::Fetch(format!(
+                    "failed to request l2geth block-trace API for block-{block_num}: {e}"
+                ));
+                reports
+                    .failure
+                    .record_blocks(None, None, block_num, block_num, &err);
+                continue;
+            }
+        };
+
+        let record = ForkImpactRecord::new(block_num as u64, &native, &overridden);
+        if let Some(e) = &record.overridden_error {
+            let err = ProveError::WitnessGeneration(format!(
+                "ccc of block-{block_num} failed under the override: {e}"
+            ));
+            reports
+                .failure
+                .record_blocks(None, None, block_num, block_num, &err);
+        }
+        fork_report.push(record);
+    }
+
+    let path = Path::new(output_dir).join("fork_impact.jsonl");
+    let result = File::create(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| fork_report.write_jsonl(BufWriter::new(file)));
+    if let Err(e) = result {
+        log::error!("chain_prover: failed to save fork impact report: {e}");
+    }
+    fork_report.log_summary();
+}
+
+/// Writes the traces of a batch or a chunk in the `load_batch` layout of the corpus, or of a block
+/// range in a dir of its own. Blocks which already have a trace file are skipped.
+async fn dump_traces(
+    l2geth: &l2geth::Client,
+    rollupscan: &rollupscan::Client,
+    reports: &Reports,
+    fetch: &FetchConfig,
+    compression: Compression,
+    args: &DumpTracesArgs,
+) {
+    let out_dir = Path::new(args.out_dir());
+    // (chunk id, chunk dir, begin block, end block)
+    let chunks: Vec<(Option<u64>, PathBuf, i64, i64)> = match args.batch {
+        Some(batch_id) => {
+            let chunks = match rollupscan.get_chunk_info_by_batch_index(batch_id).await {
+                Ok(Some(chunks)) => chunks,
+                Ok(None) => {
+                    log::warn!("chain_prover: no chunks in batch-{batch_id}");
+                    return;
+                }
+                Err(e) => {
+                    let err = ProveError::Fetch(format!(
+                        "failed to request rollupscan chunks API for batch-{batch_id}: {e}"
+                    ));
+                    reports.failure.record_batch(batch_id, &err);
+                    return;
+                }
+            };
+            let batch_dir = out_dir.join(format!("batch_{batch_id}"));
+            chunks
+                .iter()
+                .filter(|chunk| args.chunk.map_or(true, |id| id == chunk.index))
+                .map(|chunk| {
+                    (
+                        Some(chunk.index as u64),
+                        batch_dir.join(format!("chunk_{}", chunk.index)),
+                        chunk.start_block_number,
+                        chunk.end_block_number,
+                    )
+                })
+                .collect()
+        }
+        None => vec![(
+            None,
+            out_dir.to_path_buf(),
+            args.range.begin_block,
+            args.range.end_block,
+        )],
+    };
+    if chunks.is_empty() {
+        log::warn!(
+            "chain_prover: chunk-{:?} not in batch-{:?}",
+            args.chunk,
+            args.batch
+        );
+        return;
+    }
+
+    let mut skipped = 0;
+    // (chunk id, begin block, end block, error)
+    let mut failures = vec![];
+    // Blocks being dumped, removed when their task returns. Those left at the end are of tasks
+    // which panicked.
+    let mut pending = BTreeSet::new();
+    let mut joined = vec![];
+    let mut tasks = tokio::task::JoinSet::new();
+    let mut dirs = vec![];
+    for (chunk_id, dir, begin_block, end_block) in chunks {
+        if let Err(e) = fs::create_dir_all(&dir) {
+            let err = ProveError::Io(format!("failed to create dir {}: {e}", dir.display()));
+            failures.push((chunk_id, begin_block, end_block, err));
+            continue;
+        }
+        for block_num in begin_block..=end_block {
+            let exists = Compression::ALL.into_iter().any(|c| {
+                fs::metadata(trace_path(&dir, block_num as u64, c)).map_or(false, |m| m.len() > 0)
+            });
+            if exists {
+                skipped += 1;
+                continue;
+            }
+            while tasks.len() >= fetch.concurrency {
+                joined.push(tasks.join_next().await.unwrap());
+            }
+
+            let l2geth = l2geth.clone();
+            let path = trace_path(&dir, block_num as u64, compression);
+            pending.insert((chunk_id, block_num));
+            tasks.spawn(async move {
+                let result = dump_trace(&l2geth, block_num, &path).await;
+                (chunk_id, block_num, result)
+            });
+        }
+        dirs.push(dir);
+    }
+    while let Some(result) = tasks.join_next().await {
+        joined.push(result);
+    }
+
+    let mut dumped = 0;
+    for result in joined {
+        match result {
+            Ok((chunk_id, block_num, result)) => {
+                pending.remove(&(chunk_id, block_num));
+                match result {
+                    Ok(()) => dumped += 1,
+                    Err(err) => failures.push((chunk_id, block_num, block_num, err)),
+                }
+            }
+            Err(e) => log::error!("chain_prover: dump task failed: {e}"),
+        }
+    }
+    failures.extend(pending.into_iter().map(|(chunk_id, block_num)| {
+        let err = ProveError::Fetch(format!("dump task of block-{block_num} panicked"));
+        (chunk_id, block_num, block_num, err)
+    }));
+
+    for (chunk_id, begin_block, end_block, err) in &failures {
+        log::error!("chain_prover: failed to dump block-{begin_block} to block-{end_block}: {err}");
+        reports
+            .failure
+            .record_blocks(args.batch, *chunk_id, *begin_block, *end_block, err);
+    }
+    log::info!(
+        "chain_prover: dumped {dumped} traces, skipped {skipped} existing, {} failed",
+        failures.len()
+    );
+
+    // Only the dirs of this run are scanned, the rest of the manifest is kept as is.
+    let manifest_path = out_dir.join(MANIFEST_FILE);
+    let result = if manifest_path.exists() {
+        TraceManifest::load(&manifest_path)
+    } else {
+        Ok(TraceManifest::default())
+    }
+    .and_then(|mut manifest| {
+        for dir in &dirs {
+            manifest.update(out_dir, dir)?;
+        }
+        manifest.save(&manifest_path)
+    });
+    match result {
+        Ok(()) => log::info!("chain_prover: wrote {}", manifest_path.display()),
+        Err(e) => log::error!("chain_prover: failed to write trace manifest: {e:#}"),
+    }
+}
+
+async fn dump_trace(
+    l2geth: &l2geth::Client,
+    block_num: i64,
+    path: &Path,
+) -> Result<(), ProveError> {
+    let trace = l2geth
+        .get_block_trace_by_num(block_num)
+        .await
+        .map_err(|e| {
+            ProveError::Fetch(format!(
+                "failed to request l2geth block-trace API for block-{block_num}: {e}"
+            ))
+        })?;
+    serde_json::to_vec(&trace)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| write_trace_file(path, &json))
+        .map_err(|e| ProveError::Io(format!("failed to write trace of block-{block_num}: {e:#}")))
+}
+
+/// Whether `dir` is the root of a trace corpus, i.e. has a manifest or batch dirs.
+fn is_trace_corpus(dir: &Path) -> bool {
+    dir.join(MANIFEST_FILE).exists()
+        || fs::read_dir(dir).map_or(false, |entries| {
+            entries
+                .flatten()
+                .any(|entry| entry.file_name().to_string_lossy().starts_with("batch_"))
+        })
+}
+
 #[tokio::main]
 async fn main() {
-    init_env_and_log("chain_prover");
+    let args = Args::parse();
 
-    log::info!("chain_prover: BEGIN");
+    let output_dir = init_env_and_log("chain_prover");
 
-    let setting = Setting::new();
-    log::info!("chain_prover: setting = {setting:?}");
+    log::info!("chain_prover: BEGIN");
+    log::info!("chain_prover: output dir {output_dir}");
+    log::info!("chain_prover: args = {args:?}");
+    // Reject bad arguments before the (slow) warmup.
+    args.validate()
+        .unwrap_or_else(|e| panic!("chain_prover: invalid arguments: {e}"));
+    let chunk_limits = args
+        .chunk_policy
+        .as_ref()
+        .map(ChunkLimits::from_json_file)
+        .transpose()
+        .unwrap_or_else(|e| panic!("chain_prover: {e:#}"))
+        .unwrap_or_default();
+    log::info!("chain_prover: chunk policy {chunk_limits:?}");
+    let batch_limits = args
+        .batch_policy
+        .as_ref()
+        .map(BatchLimits::from_json_file)
+        .transpose()
+        .unwrap_or_else(|e| panic!("chain_prover: {e:#}"))
+        .unwrap_or_default();
+    log::info!("chain_prover: batch policy {batch_limits:?}");
 
     warmup();
 
-    let l2geth = l2geth::Client::new("chain_prover", &setting.l2geth_api_url)
-        .unwrap_or_else(|e| panic!("chain_prover: failed to initialize ethers Provider: {e}"));
-    let rollupscan = rollupscan_client::Client::new("chain_prover", &setting.rollupscan_api_url);
-
-    let test_mode = &setting.test_mode;
+    let mut l2geth = l2geth::Client::new("chain_prover", &args.l2geth_api_url)
+        .unwrap_or_else(|e| panic!("chain_prover: failed to initialize ethers Provider: {e}"))
+        .with_policy(ClientPolicy {
+            timeout: Duration::from_secs(args.l2geth_timeout),
+            max_retries: args.l2geth_retries,
+            max_requests_per_sec: args.l2geth_rate_limit,
+            ..Default::default()
+        });
+    if let Some(cache_dir) = &args.trace_cache {
+        // Only the traces of this chain are served from the cache.
+        let chain_id = match args.chain_id {
+            Some(chain_id) => chain_id,
+            None => l2geth
+                .get_chain_id()
+                .await
+                .unwrap_or_else(|e| panic!("chain_prover: failed to request chain id: {e}")),
+        };
+        let cache = TraceCache::new(cache_dir)
+            .unwrap_or_else(|e| panic!("chain_prover: failed to open trace cache: {e}"))
+            .with_compression(args.trace_compression)
+            .with_chain_id(chain_id);
+        l2geth = l2geth.with_cache(cache, args.offline);
+    }
+    let rollupscan = rollupscan::Client::new("chain_prover", &args.rollupscan_api_url);
+    let fetch = FetchConfig {
+        concurrency: args.fetch_concurrency,
+        prefetch: args.prefetch,
+    };
+    let reports = Reports::new(&output_dir);
+    #[cfg(feature = "chunk-prove")]
+    prove_utils::init_chunk_prover(&args.params_path, &args.assets_path, &output_dir);
 
-    if test_mode == "batch_prove" {
-        prove_by_batch(&l2geth, &rollupscan, setting.begin_batch, setting.end_batch).await
-    } else if test_mode == "block_prove" {
-        prove_by_block(&l2geth, setting.begin_block, setting.end_block).await
-    } else if test_mode == "txtx_ccc" {
-        txtx_ccc(&l2geth, setting.begin_block, setting.end_block).await
-    } else {
-        // Handle unknown test_mode here
-        unimplemented!("{test_mode}");
+    match args.mode {
+        Mode::BatchProve(range) => {
+            #[cfg(not(feature = "batch-prove"))]
+            prove_by_batch(&l2geth, &rollupscan, &reports, &fetch, &range).await;
+            #[cfg(feature = "batch-prove")]
+            prove_by_bundle(
+                &l2geth,
+                &rollupscan,
+                &reports,
+                &fetch,
+                &range,
+                None,
+                &args.params_path,
+                &output_dir,
+            )
+            .await;
+        }
+        #[cfg(feature = "batch-prove")]
+        Mode::BundleProve(bundle_args) => {
+            prove_by_bundle(
+                &l2geth,
+                &rollupscan,
+                &reports,
+                &fetch,
+                &bundle_args.range,
+                Some(bundle_args.bundle_size),
+                &args.params_path,
+                &output_dir,
+            )
+            .await
+        }
+        Mode::BlockProve(range) => {
+            let (begin_block, end_block) = resolve_range(&range, &l2geth).await;
+            prove_by_block(
+                &l2geth,
+                &reports,
+                &fetch,
+                chunk_limits,
+                batch_limits,
+                begin_block,
+                end_block,
+            )
+            .await
+        }
+        Mode::Simulate(sim_args) => {
+            let (l2geth, (begin_block, end_block)) = match &sim_args.trace_dir {
+                Some(trace_dir) => {
+                    let mut cache = TraceCache::new(trace_dir)
+                        .unwrap_or_else(|e| panic!("chain_prover: failed to open trace dir: {e}"));
+                    if let Some(chain_id) = args.chain_id {
+                        cache = cache.with_chain_id(chain_id);
+                    }
+                    let range = if sim_args.range.is_empty() {
+                        let (begin, end) = cache
+                            .block_range()
+                            .unwrap_or_else(|e| panic!("chain_prover: {e}"))
+                            .unwrap_or_else(|| panic!("chain_prover: trace dir is empty"));
+                        (begin as i64, end as i64)
+                    } else {
+                        (sim_args.range.begin_block, sim_args.range.end_block)
+                    };
+                    (l2geth.with_cache(cache, true), range)
+                }
+                None => {
+                    let range = resolve_range(&sim_args.range, &l2geth).await;
+                    (l2geth, range)
+                }
+            };
+            simulate(
+                &l2geth,
+                &reports,
+                &fetch,
+                chunk_limits,
+                batch_limits,
+                begin_block,
+                end_block,
+            )
+            .await
+        }
+        Mode::CccScan(scan_args) => {
+            let (begin_block, end_block) = resolve_range(&scan_args.range, &l2geth).await;
+            let config = ScanConfig {
+                fetch_concurrency: fetch.concurrency,
+                workers: scan_args.workers,
+                queue_size: scan_args.queue_size,
+                modes: scan_args.modes,
+                tolerance: args.ccc_tolerance.clone(),
+            };
+            ccc_scan(&l2geth, &reports, &config, begin_block, end_block).await
+        }
+        Mode::ForkImpact(impact_args) => {
+            let (begin_block, end_block) = resolve_range(&impact_args.range, &l2geth).await;
+            fork_impact(
+                &l2geth,
+                &reports,
+                &impact_args,
+                &output_dir,
+                begin_block,
+                end_block,
+            )
+            .await
+        }
+        Mode::DumpTraces(dump_args) => {
+            dump_traces(
+                &l2geth,
+                &rollupscan,
+                &reports,
+                &fetch,
+                args.trace_compression,
+                &dump_args,
+            )
+            .await
+        }
+        Mode::TxtxCcc(range) => {
+            let (begin_block, end_block) = resolve_range(&range, &l2geth).await;
+            txtx_ccc(
+                &l2geth,
+                &reports,
+                &args.ccc_tolerance,
+                begin_block,
+                end_block,
+            )
+            .await
+        }
     }
 
+    reports.finish();
     log::info!("chain_prover: END");
 }
 
-// TODO: change this to clap cli args
-#[derive(Debug)]
-struct Setting {
-    begin_batch: i64,
-    end_batch: i64,
-    begin_block: i64,
-    end_block: i64,
+/// Resolves the block range of a mode, see `BlockRange::resolve`. Like invalid arguments, a
+/// range which can't be resolved stops chain_prover before any block is handled.
+async fn resolve_range(range: &BlockRange, l2geth: &l2geth::Client) -> (i64, i64) {
+    range
+        .resolve(l2geth)
+        .await
+        .unwrap_or_else(|e| panic!("chain_prover: invalid block range: {e:#}"))
+}
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// l2geth JSON-RPC endpoint.
+    #[clap(
+        long = "l2geth-url",
+        env = "L2GETH_API_URL",
+        default_value = "http://127.0.0.1:8545"
+    )]
     l2geth_api_url: String,
+    /// rollupscan API base URL (e.g. `http://127.0.0.1:8560/api`), required by `batch-prove`.
+    #[clap(
+        long = "rollupscan-url",
+        env = "ROLLUPSCAN_API_URL",
+        default_value = ""
+    )]
     rollupscan_api_url: String,
-    test_mode: String,
-}
-
-impl Setting {
-    pub fn new() -> Self {
-        let l2geth_api_url =
-            env::var("L2GETH_API_URL").unwrap_or("http://127.0.0.1:8545".to_string());
-        let rollupscan_api_url = env::var("ROLLUPSCAN_API_URL");
-        let rollupscan_api_url = rollupscan_api_url.unwrap_or_default();
-        let begin_batch = env::var("PROVE_BEGIN_BATCH")
-            .ok()
-            .and_then(|n| n.parse().ok())
-            .unwrap_or_default();
-        let end_batch = env::var("PROVE_END_BATCH")
-            .ok()
-            .and_then(|n| n.parse().ok())
-            .unwrap_or_default();
-        let begin_block = env::var("PROVE_BEGIN_BLOCK")
-            .ok()
-            .and_then(|n| n.parse().ok())
-            .unwrap_or_default();
-        let end_block = env::var("PROVE_END_BLOCK")
-            .ok()
-            .and_then(|n| n.parse().ok())
-            .unwrap_or_default();
-        let test_mode = env::var("TEST_MODE")
-            .ok()
-            .and_then(|n| n.parse().ok())
-            .unwrap_or_default();
+    /// Dir to cache fetched block traces in. Cached traces are not requested again.
+    #[clap(long = "trace-cache", env = "TRACE_CACHE_DIR")]
+    trace_cache: Option<String>,
+    /// Compression of the traces written to `--trace-cache` and by `dump-traces`: none, gzip or
+    /// zstd. Traces are read whatever their compression.
+    #[clap(
+        long = "trace-compression",
+        env = "TRACE_COMPRESSION",
+        default_value = "none"
+    )]
+    trace_compression: Compression,
+    /// Serve block traces only from `--trace-cache`, never request l2geth.
+    #[clap(long, requires = "trace_cache")]
+    offline: bool,
+    /// Chain of the traces served from `--trace-cache` or `--trace-dir`. Requested from l2geth
+    /// when not given, so it's required with `--offline`.
+    #[clap(long = "chain-id", env = "CHAIN_ID")]
+    chain_id: Option<u64>,
+    /// Number of block traces requested concurrently.
+    #[clap(
+        long = "fetch-concurrency",
+        env = "FETCH_CONCURRENCY",
+        default_value_t = 4
+    )]
+    fetch_concurrency: usize,
+    /// Number of fetched block traces buffered ahead of proving.
+    #[clap(long, env = "FETCH_PREFETCH", default_value_t = 16)]
+    prefetch: usize,
+    /// Timeout in seconds of each l2geth request.
+    #[clap(long = "l2geth-timeout", env = "L2GETH_TIMEOUT", default_value_t = 120)]
+    l2geth_timeout: u64,
+    /// Retries of an l2geth request failed by a retriable error (no answer, rate limited, ...).
+    #[clap(long = "l2geth-retries", env = "L2GETH_RETRIES", default_value_t = 3)]
+    l2geth_retries: u32,
+    /// Max l2geth requests per second.
+    #[clap(long = "l2geth-rate-limit", env = "L2GETH_RATE_LIMIT")]
+    l2geth_rate_limit: Option<f64>,
+    /// Rows each sub-circuit may be under-estimated by, compared with the optimal CCC, e.g.
+    /// `default=0,bytecode=1,error_ratio=0.01`.
+    #[clap(
+        long = "ccc-tolerance",
+        env = "CCC_TOLERANCE",
+        default_value = "bytecode=1"
+    )]
+    ccc_tolerance: CccTolerance,
+    /// JSON file of the chunk proposer limits (see `ChunkLimits`), or the config file of a
+    /// deployed rollup-relayer, used when building chunks from blocks. Without it, chunks are only
+    /// closed when they don't fit in the circuits.
+    #[clap(long = "chunk-policy", env = "CHUNK_POLICY")]
+    chunk_policy: Option<String>,
+    /// JSON file of the batch proposer limits (see `BatchLimits`), used when building batches
+    /// from chunks. Defaults to `MAX_AGG_SNARKS` chunks per full blob.
+    #[clap(long = "batch-policy", env = "BATCH_POLICY")]
+    batch_policy: Option<String>,
+    /// Params dir of the chunk and batch provers.
+    #[cfg(feature = "chunk-prove")]
+    #[clap(long = "params", env = "PARAMS_DIR", default_value = "params")]
+    params_path: String,
+    /// Assets dir of the chunk prover.
+    #[cfg(feature = "chunk-prove")]
+    #[clap(long = "assets", env = "ASSETS_DIR", default_value = "test_assets")]
+    assets_path: String,
+    #[clap(subcommand)]
+    mode: Mode,
+}
 
-        Self {
-            begin_batch,
-            end_batch,
-            begin_block,
-            end_block,
-            l2geth_api_url,
-            rollupscan_api_url,
-            test_mode,
+impl Args {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.offline && self.chain_id.is_none() {
+            bail!("--chain-id is required with --offline, to check the cached traces");
+        }
+        if self.offline && matches!(self.mode, Mode::TxtxCcc(_)) {
+            bail!("txtx-ccc needs tx-by-tx traces, which are not cached");
+        }
+        if self.offline && matches!(self.mode, Mode::ForkImpact(_)) {
+            bail!("fork-impact needs overridden traces, which are not cached");
+        }
+        if self.mode.batch_range().is_some() && self.rollupscan_api_url.is_empty() {
+            bail!("--rollupscan-url is required to prove batches");
+        }
+        if matches!(&self.mode, Mode::DumpTraces(args) if args.batch.is_some())
+            && self.rollupscan_api_url.is_empty()
+        {
+            bail!("--rollupscan-url is required to dump the traces of a batch");
+        }
+        if self.fetch_concurrency == 0 {
+            bail!("--fetch-concurrency must be positive");
+        }
+        if self.l2geth_rate_limit.map_or(false, |limit| limit <= 0.0) {
+            bail!("--l2geth-rate-limit must be positive");
+        }
+        self.mode.validate()
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Mode {
+    /// Build chunks and batches from a block range, like the production proposers do.
+    BlockProve(BlockRange),
+    /// Prove batches using the chunks committed on chain (fetched from rollupscan).
+    BatchProve(BatchRange),
+    /// Prove batches like `batch-prove`, and aggregate consecutive batch proofs into bundles.
+    #[cfg(feature = "batch-prove")]
+    BundleProve(BundleArgs),
+    /// Compare tx-by-tx CCC row usage with whole-block row usage.
+    TxtxCcc(BlockRange),
+    /// Run CCC on each block of a range, with a pool of checkers.
+    CccScan(CccScanArgs),
+    /// Only build chunks and batches from a block range, and log their utilization stats.
+    Simulate(SimulateArgs),
+    /// Compare CCC of each block of a range as traced natively and with a fork override.
+    ForkImpact(ForkImpactArgs),
+    /// Download the traces of a batch, a chunk or a block range into a trace corpus dir.
+    DumpTraces(DumpTracesArgs),
+}
+
+impl Mode {
+    fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            Mode::BlockProve(range) | Mode::TxtxCcc(range) => range.validate(),
+            Mode::CccScan(args) => {
+                if args.modes.is_empty() {
+                    bail!("--modes must not be empty");
+                }
+                args.range.validate()
+            }
+            Mode::Simulate(args) => args.range.validate(),
+            Mode::ForkImpact(args) => {
+                if args.fork.is_none() && args.overrides.is_empty() {
+                    bail!("either --fork or --override is required");
+                }
+                args.range.validate()
+            }
+            Mode::DumpTraces(args) => {
+                match args.batch {
+                    Some(batch) if batch < 0 => bail!("invalid batch {batch}"),
+                    Some(_) if !args.range.is_empty() => {
+                        bail!("--batch and --begin/--end are exclusive")
+                    }
+                    Some(_) => Ok(()),
+                    None if args.range.is_empty() => {
+                        bail!("either --batch or --begin/--end is required")
+                    }
+                    None => {
+                        // A block range isn't in the batch/chunk layout, so it would mix flat trace
+                        // files into the corpus.
+                        let Some(out_dir) = &args.out_dir else {
+                            bail!("--out-dir is required to dump a block range");
+                        };
+                        if is_trace_corpus(Path::new(out_dir)) {
+                            bail!("--out-dir {out_dir} is a trace corpus, not for a block range");
+                        }
+                        args.range.validate()
+                    }
+                }
+            }
+            Mode::BatchProve(range) => range.validate(),
+            #[cfg(feature = "batch-prove")]
+            Mode::BundleProve(args) => {
+                if args.bundle_size == 0 {
+                    bail!("--bundle-size must be positive");
+                }
+                args.range.validate()
+            }
+        }
+    }
+
+    fn batch_range(&self) -> Option<&BatchRange> {
+        match self {
+            Mode::BatchProve(range) => Some(range),
+            #[cfg(feature = "batch-prove")]
+            Mode::BundleProve(args) => Some(&args.range),
+            _ => None,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct BlockRange {
+    /// First block to handle. Use 0 for both ends to handle the blocks of the last 24 hours.
+    #[clap(long = "begin", env = "PROVE_BEGIN_BLOCK", default_value_t = 0)]
+    begin_block: i64,
+    /// Last block to handle (inclusive).
+    #[clap(long = "end", env = "PROVE_END_BLOCK", default_value_t = 0)]
+    end_block: i64,
+}
+
+impl BlockRange {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.begin_block < 0 || self.begin_block > self.end_block {
+            bail!(
+                "invalid block range {} to {}",
+                self.begin_block,
+                self.end_block
+            );
+        }
+        Ok(())
+    }
+
+    /// Whether no range is given, see `resolve`.
+    fn is_empty(&self) -> bool {
+        self.begin_block == 0 && self.end_block == 0
+    }
+
+    /// Returns the first and the last block of the range, requesting the latest block from
+    /// l2geth when no range is given.
+    async fn resolve(&self, l2geth: &l2geth::Client) -> anyhow::Result<(i64, i64)> {
+        if !self.is_empty() {
+            return Ok((self.begin_block, self.end_block));
+        }
+
+        // Blocks within last 24 hours
+        let block_num = 24 * 1200;
+        log::info!("use latest {block_num} blocks");
+        let latest_block = l2geth
+            .get_block_number()
+            .await
+            .context("failed to request the latest block number")?;
+        let range = Self {
+            begin_block: (latest_block as i64 - block_num).max(0),
+            end_block: latest_block as i64,
+        };
+        range.validate()?;
+        Ok((range.begin_block, range.end_block))
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct CccScanArgs {
+    #[clap(flatten)]
+    range: BlockRange,
+    /// Number of blocks checked concurrently.
+    #[clap(long, env = "CCC_WORKERS", default_value_t = 4)]
+    workers: usize,
+    /// Number of fetched blocks waiting for a checker.
+    #[clap(long = "queue-size", env = "CCC_QUEUE_SIZE", default_value_t = 16)]
+    queue_size: usize,
+    /// CCC modes to run, the others are compared with the first one when it's `optimal`.
+    #[clap(
+        long,
+        env = "CCC_MODES",
+        value_delimiter = ',',
+        default_value = "optimal,signer"
+    )]
+    modes: Vec<CCCMode>,
+}
+
+#[derive(clap::Args, Debug)]
+struct SimulateArgs {
+    #[clap(flatten)]
+    range: BlockRange,
+    /// Dir of block traces (`block_<n>.json`, e.g. a `--trace-cache` dir) to read instead of
+    /// requesting l2geth. Without `--begin` and `--end`, all blocks of the dir are used.
+    #[clap(long = "trace-dir", env = "TRACE_DIR")]
+    trace_dir: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ForkImpactArgs {
+    #[clap(flatten)]
+    range: BlockRange,
+    /// Fork to enable from genesis when tracing: bernoulli, curie, darwin or darwin-v2.
+    #[clap(long, env = "FORK")]
+    fork: Option<Fork>,
+    /// Other chain config overrides, e.g. `--override curieBlock=1`. Values are parsed as JSON,
+    /// or else taken as strings.
+    #[clap(long = "override")]
+    overrides: Vec<ConfigOverride>,
+}
+
+/// `<key>=<value>` override of a field of the l2geth chain config.
+#[derive(Debug, Clone)]
+struct ConfigOverride {
+    key: String,
+    value: serde_json::Value,
+}
+
+impl FromStr for ConfigOverride {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let Some((key, value)) = s.split_once('=') else {
+            bail!("expect `<key>=<value>`, got {s:?}");
+        };
+        let value = serde_json::from_str(value)
+            .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+        Ok(Self {
+            key: key.trim().to_string(),
+            value,
+        })
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct DumpTracesArgs {
+    /// Block range to dump, written directly in `--out-dir`, which is then required and must not
+    /// be a trace corpus.
+    #[clap(flatten)]
+    range: BlockRange,
+    /// Batch to dump, written as `<out-dir>/batch_<batch>/chunk_<chunk>/block_<block>.json`.
+    #[clap(long)]
+    batch: Option<i64>,
+    /// Only dump this chunk of `--batch`. Chunks are looked up in the chunks of a batch, as
+    /// rollupscan has no API to look a chunk up by its index.
+    #[clap(long, requires = "batch")]
+    chunk: Option<i64>,
+    /// Dir to write into, whose `manifest.json` is updated with the dirs written. Defaults to the
+    /// trace corpus when dumping a batch.
+    #[clap(long = "out-dir", env = "TRACE_DUMP_DIR")]
+    out_dir: Option<String>,
+}
+
+impl DumpTracesArgs {
+    const TRACE_CORPUS: &'static str = "integration/tests/extra_traces";
+
+    fn out_dir(&self) -> &str {
+        self.out_dir.as_deref().unwrap_or(Self::TRACE_CORPUS)
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct BatchRange {
+    /// First batch to prove.
+    #[clap(long = "begin", env = "PROVE_BEGIN_BATCH", default_value_t = 0)]
+    begin_batch: i64,
+    /// Last batch to prove (inclusive).
+    #[clap(long = "end", env = "PROVE_END_BATCH", default_value_t = 0)]
+    end_batch: i64,
+    /// Batch codec version, used when rollupscan doesn't tell the version of a batch.
+    #[clap(long = "batch-version", env = "BATCH_VERSION", default_value_t = 4)]
+    #[cfg_attr(not(feature = "batch-prove"), allow(dead_code))]
+    batch_version: u8,
+    /// Journal of finished chunks and batches, with chunk proofs saved in `chunk_proofs` next to
+    /// it. Unlike the output dir, the default doesn't change between runs, so a run can be
+    /// resumed without knowing where the previous one wrote.
+    #[clap(
+        long,
+        env = "PROVE_JOURNAL",
+        default_value = "outputs/chain_prover/journal.jsonl"
+    )]
+    journal: String,
+    /// Skip the chunks and batches finished according to `--journal`.
+    #[clap(long)]
+    resume: bool,
+}
+
+impl BatchRange {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.begin_batch < 0 || self.begin_batch > self.end_batch {
+            bail!(
+                "invalid batch range {} to {}",
+                self.begin_batch,
+                self.end_batch
+            );
         }
+        Ok(())
     }
 }
+
+#[cfg(feature = "batch-prove")]
+#[derive(clap::Args, Debug)]
+struct BundleArgs {
+    #[clap(flatten)]
+    range: BatchRange,
+    /// Number of consecutive batches aggregated into a bundle.
+    #[clap(long = "bundle-size", env = "BUNDLE_SIZE", default_value_t = 3)]
+    bundle_size: usize,
+}
@@ -0,0 +1,254 @@
+//! Typed errors of chain_prover, and the per-run report of failures.
+
+#[cfg(feature = "chunk-prove")]
+use integration::prove::ProveFailure;
+use prover::eth_types::l2_types::BlockTrace;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorClass {
+    Fetch,
+    Io,
+    WitnessGeneration,
+    CccOverflow,
+    BlobOverflow,
+    MockProve,
+    RealProve,
+    Verify,
+}
+
+#[derive(Debug, Clone)]
+// Which errors are raised depends on the prove features, e.g. only the mock prover checks
+// chunks before proving, and only the real provers verify proofs.
+#[allow(dead_code)]
+pub enum ProveError {
+    /// Failed to request l2geth or rollupscan.
+    Fetch(String),
+    /// Failed to read or write local files, e.g. dumped traces.
+    Io(String),
+    /// Failed to build the witness block from traces.
+    WitnessGeneration(String),
+    /// The chunk doesn't fit in the circuits.
+    CccOverflow(String),
+    /// The chunk doesn't fit in a batch.
+    BlobOverflow(String),
+    /// Mock prover found unsatisfied constraints.
+    MockProve(String),
+    /// Failed to generate the real proof.
+    RealProve(String),
+    /// Generated proof doesn't verify.
+    Verify(String),
+}
+
+#[cfg(feature = "chunk-prove")]
+impl From<ProveFailure> for ProveError {
+    fn from(failure: ProveFailure) -> Self {
+        match failure {
+            ProveFailure::Prove(msg) => Self::RealProve(msg),
+            ProveFailure::Verify(msg) => Self::Verify(msg),
+        }
+    }
+}
+
+impl ProveError {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::Fetch(_) => ErrorClass::Fetch,
+            Self::Io(_) => ErrorClass::Io,
+            Self::WitnessGeneration(_) => ErrorClass::WitnessGeneration,
+            Self::CccOverflow(_) => ErrorClass::CccOverflow,
+            Self::BlobOverflow(_) => ErrorClass::BlobOverflow,
+            Self::MockProve(_) => ErrorClass::MockProve,
+            Self::RealProve(_) => ErrorClass::RealProve,
+            Self::Verify(_) => ErrorClass::Verify,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Fetch(msg)
+            | Self::Io(msg)
+            | Self::WitnessGeneration(msg)
+            | Self::CccOverflow(msg)
+            | Self::BlobOverflow(msg)
+            | Self::MockProve(msg)
+            | Self::RealProve(msg)
+            | Self::Verify(msg) => msg,
+        }
+    }
+}
+
+impl fmt::Display for ProveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.class(), self.message())
+    }
+}
+
+impl std::error::Error for ProveError {}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Failure {
+    pub batch_id: Option<i64>,
+    pub chunk_id: Option<u64>,
+    pub begin_block: Option<i64>,
+    pub end_block: Option<i64>,
+    pub class: ErrorClass,
+    pub message: String,
+    /// Dir of the dumped traces, loadable by `test_util::load_chunk` (e.g. `trace_prover -t`).
+    pub trace_path: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    counts: BTreeMap<ErrorClass, usize>,
+    failures: Vec<Failure>,
+}
+
+/// Failures of a run, written to `<output dir>/failure_report.json` on every new failure.
+pub struct FailureReport {
+    path: PathBuf,
+    trace_dir: PathBuf,
+    report: Mutex<Report>,
+}
+
+impl FailureReport {
+    pub fn new(output_dir: &str) -> Self {
+        Self {
+            path: Path::new(output_dir).join("failure_report.json"),
+            trace_dir: Path::new(output_dir).join("failed_traces"),
+            report: Mutex::new(Report::default()),
+        }
+    }
+
+    /// Records a failure of a block range, whose traces are not (all) available.
+    pub fn record_blocks(
+        &self,
+        batch_id: Option<i64>,
+        chunk_id: Option<u64>,
+        begin_block: i64,
+        end_block: i64,
+        err: &ProveError,
+    ) {
+        self.record(Failure {
+            batch_id,
+            chunk_id,
+            begin_block: Some(begin_block),
+            end_block: Some(end_block),
+            class: err.class(),
+            message: err.message().to_string(),
+            trace_path: None,
+        });
+    }
+
+    /// Records a failure of a chunk, and dumps its traces (if any) to reproduce it.
+    pub fn record_chunk(
+        &self,
+        batch_id: Option<i64>,
+        chunk_id: u64,
+        block_traces: &[BlockTrace],
+        err: &ProveError,
+    ) {
+        let block_num = |trace: Option<&BlockTrace>| {
+            trace
+                .and_then(|t| t.header.number)
+                .map(|n| n.as_u64() as i64)
+        };
+        let trace_path = (!block_traces.is_empty())
+            .then(|| self.dump_traces(batch_id, chunk_id, block_traces))
+            .flatten();
+
+        self.record(Failure {
+            batch_id,
+            chunk_id: Some(chunk_id),
+            begin_block: block_num(block_traces.first()),
+            end_block: block_num(block_traces.last()),
+            class: err.class(),
+            message: err.message().to_string(),
+            trace_path,
+        });
+    }
+
+    pub fn record_batch(&self, batch_id: i64, err: &ProveError) {
+        self.record(Failure {
+            batch_id: Some(batch_id),
+            chunk_id: None,
+            begin_block: None,
+            end_block: None,
+            class: err.class(),
+            message: err.message().to_string(),
+            trace_path: None,
+        });
+    }
+
+    /// Logs the failure counts of each class.
+    pub fn log_summary(&self) {
+        let report = self.report.lock().unwrap();
+        if report.failures.is_empty() {
+            log::info!("failure report: no failures");
+            return;
+        }
+        log::error!(
+            "failure report: {} failures {:?}, see {}",
+            report.failures.len(),
+            report.counts,
+            self.path.display()
+        );
+    }
+
+    fn record(&self, failure: Failure) {
+        log::error!("failure report: {failure:?}");
+
+        let mut report = self.report.lock().unwrap();
+        *report.counts.entry(failure.class).or_default() += 1;
+        report.failures.push(failure);
+        let result = serde_json::to_vec_pretty(&*report)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(fs::write(&self.path, bytes)?));
+        if let Err(e) = result {
+            log::error!(
+                "failure report: failed to write {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+
+    fn dump_traces(
+        &self,
+        batch_id: Option<i64>,
+        chunk_id: u64,
+        block_traces: &[BlockTrace],
+    ) -> Option<String> {
+        let dir = self
+            .trace_dir
+            .join(format!("batch_{}", batch_id.unwrap_or_default()))
+            .join(format!("chunk_{chunk_id}"));
+        let result = fs::create_dir_all(&dir)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| {
+                for trace in block_traces {
+                    let block_num = trace.header.number.unwrap_or_default().as_u64();
+                    let path = dir.join(format!("block_{block_num}.json"));
+                    fs::write(path, serde_json::to_vec(trace)?)?;
+                }
+                Ok(())
+            });
+
+        match result {
+            Ok(_) => Some(dir.to_string_lossy().into_owned()),
+            Err(e) => {
+                log::error!(
+                    "failure report: failed to dump traces to {}: {e}",
+                    dir.display()
+                );
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,35 @@
+// Serve l2geth trace APIs from a local trace dir, so chain_prover can run without a node:
+//   fake_l2geth --trace-dir tests/extra_traces &
+//   chain_prover --l2geth-url http://127.0.0.1:8545 block-prove --begin <n> --end <m>
+
+use clap::Parser;
+use integration::fake_l2geth::FakeL2geth;
+use prover::init_env_and_log;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Dir of `block_<n>.json` traces, searched recursively.
+    #[clap(long = "trace-dir", default_value = "integration/tests/extra_traces")]
+    trace_dir: String,
+    /// Address to listen on.
+    #[clap(long, default_value = "127.0.0.1:8545")]
+    addr: String,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    init_env_and_log("fake_l2geth");
+
+    let fake_l2geth = FakeL2geth::from_trace_dir(&args.trace_dir)
+        .unwrap_or_else(|e| panic!("fake_l2geth: failed to index trace dir: {e}"));
+    let addr = fake_l2geth
+        .spawn(&args.addr)
+        .await
+        .unwrap_or_else(|e| panic!("fake_l2geth: failed to serve: {e}"));
+    log::info!("fake_l2geth: listening on {addr}");
+
+    tokio::signal::ctrl_c().await.unwrap();
+    log::info!("fake_l2geth: END");
+}
@@ -0,0 +1,38 @@
+// Serve rollupscan APIs from a local fixture dir of `batch_<n>.json`:
+//   fake_rollupscan --fixture-dir integration/tests/test_data/rollupscan &
+//   chain_prover --rollupscan-url http://127.0.0.1:8560/api batch-prove --begin <n> --end <m>
+
+use clap::Parser;
+use integration::fake_rollupscan::FakeRollupscan;
+use prover::init_env_and_log;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Dir of `batch_<n>.json` fixtures.
+    #[clap(
+        long = "fixture-dir",
+        default_value = "integration/tests/test_data/rollupscan"
+    )]
+    fixture_dir: String,
+    /// Address to listen on.
+    #[clap(long, default_value = "127.0.0.1:8560")]
+    addr: String,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    init_env_and_log("fake_rollupscan");
+
+    let fake_rollupscan = FakeRollupscan::from_fixture_dir(&args.fixture_dir)
+        .unwrap_or_else(|e| panic!("fake_rollupscan: failed to load fixtures: {e}"));
+    let addr = fake_rollupscan
+        .spawn(&args.addr)
+        .await
+        .unwrap_or_else(|e| panic!("fake_rollupscan: failed to serve: {e}"));
+    log::info!("fake_rollupscan: listening on http://{addr}/api");
+
+    tokio::signal::ctrl_c().await.unwrap();
+    log::info!("fake_rollupscan: END");
+}
@@ -0,0 +1,205 @@
+//! Progress journal of chain_prover, so an interrupted run can be resumed.
+//!
+//! The journal is a JSON Lines file, one `Entry` per finished chunk or batch. Later entries
+//! of the same chunk/batch override earlier ones.
+
+use anyhow::{Context, Result};
+use prover::{eth_types::l2_types::BlockTrace, ChunkProofV2};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Chunk,
+    Batch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Proved,
+    Failed,
+    Skipped,
+    CccOnly,
+}
+
+impl Status {
+    /// Failed work is retried when resuming, all other work is done.
+    pub fn is_done(&self) -> bool {
+        *self != Status::Failed
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub kind: Kind,
+    pub batch_id: i64,
+    /// `None` for batch entries.
+    pub chunk_id: Option<u64>,
+    pub status: Status,
+    pub proof_path: Option<String>,
+    pub elapsed_ms: u64,
+    /// Only for chunk entries. Missing in journals written before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<ChunkContext>,
+}
+
+/// Batch header fields collected from the block traces of a chunk, so a batch can be built
+/// without fetching the traces of its chunks finished in a previous run.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ChunkContext {
+    pub start_l1_queue_index: u64,
+    pub l1_message_popped: u64,
+    pub last_block_timestamp: u64,
+}
+
+impl ChunkContext {
+    pub fn from_traces(block_traces: &[BlockTrace]) -> Self {
+        Self {
+            start_l1_queue_index: block_traces
+                .first()
+                .map_or(0, |trace| trace.start_l1_queue_index),
+            l1_message_popped: block_traces.iter().map(|trace| trace.num_l1_txs()).sum(),
+            last_block_timestamp: block_traces
+                .last()
+                .map_or(0, |trace| trace.header.timestamp.as_u64()),
+        }
+    }
+}
+
+pub struct Journal {
+    file: File,
+    proof_dir: PathBuf,
+    entries: HashMap<(Kind, i64, Option<u64>), Entry>,
+}
+
+impl Journal {
+    /// Opens the journal at `path`. Entries of a previous run are loaded when `resume` is set,
+    /// otherwise the journal is truncated.
+    pub fn open(path: impl AsRef<Path>, resume: bool) -> Result<Self> {
+        let path = path.as_ref();
+        let proof_dir = path
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join("chunk_proofs");
+        fs::create_dir_all(&proof_dir)?;
+
+        let mut entries = HashMap::new();
+        if resume && path.exists() {
+            let reader = BufReader::new(File::open(path)?);
+            for (line_idx, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // The last line may be truncated if the previous run was killed.
+                match serde_json::from_str::<Entry>(&line) {
+                    Ok(entry) => {
+                        entries.insert((entry.kind, entry.batch_id, entry.chunk_id), entry);
+                    }
+                    Err(e) => log::warn!(
+                        "journal: ignore malformed line {} of {}: {e}",
+                        line_idx + 1,
+                        path.display()
+                    ),
+                }
+            }
+            log::info!(
+                "journal: resuming from {} with {} entries",
+                path.display(),
+                entries.len()
+            );
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resume)
+            .truncate(!resume)
+            .open(path)
+            .with_context(|| format!("failed to open journal {}", path.display()))?;
+
+        Ok(Self {
+            file,
+            proof_dir,
+            entries,
+        })
+    }
+
+    pub fn chunk(&self, batch_id: i64, chunk_id: u64) -> Option<&Entry> {
+        self.entries.get(&(Kind::Chunk, batch_id, Some(chunk_id)))
+    }
+
+    pub fn batch(&self, batch_id: i64) -> Option<&Entry> {
+        self.entries.get(&(Kind::Batch, batch_id, None))
+    }
+
+    pub fn record_chunk(
+        &mut self,
+        batch_id: i64,
+        chunk_id: u64,
+        status: Status,
+        proof: Option<&ChunkProofV2>,
+        context: Option<ChunkContext>,
+        elapsed: Duration,
+    ) -> Result<()> {
+        let proof_path = proof
+            .map(|proof| {
+                let path = self.proof_dir.join(format!("chunk_{chunk_id}.json"));
+                let file = File::create(&path)?;
+                serde_json::to_writer(file, proof)?;
+                anyhow::Ok(path.to_string_lossy().into_owned())
+            })
+            .transpose()?;
+
+        self.record(Entry {
+            kind: Kind::Chunk,
+            batch_id,
+            chunk_id: Some(chunk_id),
+            status,
+            proof_path,
+            elapsed_ms: elapsed.as_millis() as u64,
+            context,
+        })
+    }
+
+    pub fn record_batch(&mut self, batch_id: i64, status: Status, elapsed: Duration) -> Result<()> {
+        self.record(Entry {
+            kind: Kind::Batch,
+            batch_id,
+            chunk_id: None,
+            status,
+            proof_path: None,
+            elapsed_ms: elapsed.as_millis() as u64,
+            context: None,
+        })
+    }
+
+    /// Loads the chunk proof saved by `record_chunk`.
+    pub fn load_chunk_proof(entry: &Entry) -> Result<Option<ChunkProofV2>> {
+        entry
+            .proof_path
+            .as_ref()
+            .map(|path| {
+                prover::read_json_deep(path)
+                    .with_context(|| format!("failed to load chunk proof {path}"))
+            })
+            .transpose()
+    }
+
+    fn record(&mut self, entry: Entry) -> Result<()> {
+        writeln!(self.file, "{}", serde_json::to_string(&entry)?)?;
+        self.file.flush()?;
+        self.entries
+            .insert((entry.kind, entry.batch_id, entry.chunk_id), entry);
+
+        Ok(())
+    }
+}
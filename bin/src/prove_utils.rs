@@ -1,88 +1,329 @@
+use crate::failure::ProveError;
+use integration::capacity_checker::panic_message;
 use prover::{eth_types::l2_types::BlockTrace, ChunkProofV2};
 use std::panic::{catch_unwind, AssertUnwindSafe};
 
+#[cfg(not(any(feature = "inner-prove", feature = "chunk-prove")))]
+use prover::{
+    calculate_row_usage_of_witness_block, chunk_trace_to_witness_block,
+    zkevm_circuits::witness::Block, RowUsage,
+};
+
+#[cfg(feature = "chunk-prove")]
+use halo2_proofs::{halo2curves::bn256::Bn256, poly::kzg::commitment::ParamsKZG};
+#[cfg(feature = "chunk-prove")]
+use integration::prove::ProveFailure;
+#[cfg(feature = "chunk-prove")]
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, OnceLock, PoisonError},
+};
+
+#[cfg(feature = "batch-prove")]
+use crate::failure::FailureReport;
 #[cfg(feature = "batch-prove")]
-use prover::{BatchHeader, MAX_AGG_SNARKS};
+use prover::{BatchHeader, BatchProofV2, BatchProver, BatchProvingTask, MAX_AGG_SNARKS};
 
 #[cfg(feature = "batch-prove")]
-pub fn prove_batch(
-    id: &str,
+fn batch_proving_task(
     chunk_proofs: Vec<ChunkProofV2>,
     batch_header: BatchHeader<MAX_AGG_SNARKS>,
-) {
+) -> BatchProvingTask {
     use integration::prove::get_blob_from_chunks;
     use itertools::Itertools;
-    use prover::BatchProvingTask;
 
     let chunk_infos = chunk_proofs
         .iter()
         .map(|p| p.inner.chunk_info().clone())
         .collect_vec();
     let blob_bytes = get_blob_from_chunks(&chunk_infos);
-    let batch = BatchProvingTask {
+    BatchProvingTask {
         chunk_proofs,
         batch_header,
         blob_bytes,
-    };
-    let result = catch_unwind(AssertUnwindSafe(|| prover::batch_prove(id, batch)));
+    }
+}
 
-    match result {
-        Ok(_) => log::info!("{id}: succeeded to prove batch"),
-        Err(err) => {
-            let panic_err = if let Some(s) = err.downcast_ref::<String>() {
-                s.to_string()
-            } else if let Some(s) = err.downcast_ref::<&str>() {
-                s.to_string()
-            } else {
-                format!("unable to get panic info {err:?}")
-            };
-            log::error!("{id}: failed to prove batch:\n{panic_err:?}");
+/// Proves batches with a local batch prover, and with a `bundle_size`, aggregates the proofs of
+/// consecutive batches into bundles of that size.
+#[cfg(feature = "batch-prove")]
+pub struct BundleProver<'params> {
+    params_map: &'params BTreeMap<u32, ParamsKZG<Bn256>>,
+    failure_report: &'params FailureReport,
+    output_dir: String,
+    bundle_size: Option<usize>,
+    // Constructed with the chunk protocol of the first proved chunk.
+    batch_prover: Option<BatchProver<'params>>,
+    // Proofs of consecutive batches, not bundled yet.
+    batch_proofs: Vec<(i64, BatchProofV2)>,
+}
+
+#[cfg(feature = "batch-prove")]
+impl<'params> BundleProver<'params> {
+    pub fn new(
+        params_map: &'params BTreeMap<u32, ParamsKZG<Bn256>>,
+        failure_report: &'params FailureReport,
+        output_dir: &str,
+        bundle_size: Option<usize>,
+    ) -> Self {
+        Self {
+            params_map,
+            failure_report,
+            output_dir: output_dir.to_string(),
+            bundle_size,
+            batch_prover: None,
+            batch_proofs: Vec::new(),
+        }
+    }
+
+    /// Proves a batch, and proves a bundle when enough consecutive batches are proved.
+    pub fn add_batch(
+        &mut self,
+        batch_id: i64,
+        chunk_proofs: Vec<ChunkProofV2>,
+        batch_header: BatchHeader<MAX_AGG_SNARKS>,
+    ) -> Result<(), ProveError> {
+        use integration::prove::{new_batch_prover, try_prove_and_verify_batch};
+
+        if let Some((last_batch_id, _)) = self.batch_proofs.last() {
+            if last_batch_id + 1 != batch_id {
+                log::warn!(
+                    "chain_prover: batch-{batch_id} doesn't follow batch-{last_batch_id}, close the pending bundle"
+                );
+                self.prove_bundle();
+            }
+        }
+
+        let id = format!("chain_prover: batch-{batch_id}");
+        let batch_prover = self.batch_prover.get_or_insert_with(|| {
+            // Dump chunk-protocol for batch proving.
+            chunk_proofs[0].dump(&self.output_dir, "0").unwrap();
+            new_batch_prover(self.params_map, &self.output_dir)
+        });
+        let batch = batch_proving_task(chunk_proofs, batch_header);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            try_prove_and_verify_batch(self.params_map, &self.output_dir, batch_prover, batch)
+        }))
+        .unwrap_or_else(|err| Err(ProveFailure::Prove(panic_message(err))));
+
+        match result {
+            Ok(batch_proof) => {
+                log::info!("{id}: succeeded to prove batch");
+                if let Some(bundle_size) = self.bundle_size {
+                    self.batch_proofs.push((batch_id, batch_proof));
+                    if self.batch_proofs.len() >= bundle_size {
+                        self.prove_bundle();
+                    }
+                }
+                Ok(())
+            }
+            Err(err) => {
+                log::error!("{id}: failed to prove batch:\n{err}");
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Bundles the pending batch proofs, if any.
+    pub fn finish(&mut self) {
+        self.prove_bundle();
+    }
+
+    fn prove_bundle(&mut self) {
+        use integration::prove::try_prove_and_verify_bundle;
+        use prover::BundleProvingTask;
+
+        let (Some(&(first_batch_id, _)), Some(&(last_batch_id, _))) =
+            (self.batch_proofs.first(), self.batch_proofs.last())
+        else {
+            return;
+        };
+        let id = format!("chain_prover: bundle of batch-{first_batch_id} to batch-{last_batch_id}");
+        let bundle = BundleProvingTask {
+            batch_proofs: self
+                .batch_proofs
+                .drain(..)
+                .map(|(_, batch_proof)| batch_proof)
+                .collect(),
+        };
+
+        let batch_prover = self
+            .batch_prover
+            .as_mut()
+            .expect("batch prover is constructed with the first batch");
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            try_prove_and_verify_bundle(&self.output_dir, batch_prover, bundle)
+        }))
+        .unwrap_or_else(|err| Err(ProveFailure::Prove(panic_message(err))));
+
+        match result {
+            Ok(()) => log::info!("{id}: succeeded to prove bundle"),
+            Err(err) => {
+                log::error!("{id}: failed to prove bundle:\n{err}");
+                self.failure_report
+                    .record_batch(last_batch_id, &ProveError::from(err));
+            }
         }
     }
 }
 
-pub fn prove_chunk(id: &str, traces: Vec<BlockTrace>) -> Option<ChunkProofV2> {
+#[cfg(feature = "chunk-prove")]
+struct ChunkProverDirs {
+    params: String,
+    assets: String,
+    output: String,
+}
+
+/// The chunk prover of `prove_chunk`, with the params it borrows.
+#[cfg(feature = "chunk-prove")]
+struct LocalChunkProver {
+    params_map: &'static BTreeMap<u32, ParamsKZG<Bn256>>,
+    prover: prover::ChunkProver<'static>,
+    output_dir: String,
+}
+
+#[cfg(feature = "chunk-prove")]
+static CHUNK_PROVER_DIRS: OnceLock<ChunkProverDirs> = OnceLock::new();
+#[cfg(feature = "chunk-prove")]
+static CHUNK_PROVER: OnceLock<Mutex<LocalChunkProver>> = OnceLock::new();
+
+/// Sets the dirs of the chunk prover of `prove_chunk`. The prover is only constructed by the
+/// first chunk to prove, so runs which don't prove never load the params.
+#[cfg(feature = "chunk-prove")]
+pub fn init_chunk_prover(params_dir: &str, assets_dir: &str, output_dir: &str) {
+    let dirs = ChunkProverDirs {
+        params: params_dir.to_string(),
+        assets: assets_dir.to_string(),
+        output: output_dir.to_string(),
+    };
+    if CHUNK_PROVER_DIRS.set(dirs).is_err() {
+        log::warn!("chain_prover: chunk prover is already initialized");
+    }
+}
+
+#[cfg(feature = "chunk-prove")]
+fn chunk_prover() -> &'static Mutex<LocalChunkProver> {
+    CHUNK_PROVER.get_or_init(|| {
+        use itertools::Itertools;
+        use prover::CHUNK_PROVER_DEGREES;
+
+        let dirs = CHUNK_PROVER_DIRS
+            .get()
+            .expect("init_chunk_prover is called before proving");
+        // The prover borrows the params for the rest of the run.
+        let params_map = Box::leak(Box::new(prover::Prover::load_params_map(
+            &dirs.params,
+            &CHUNK_PROVER_DEGREES.iter().copied().collect_vec(),
+        )));
+        let prover = prover::ChunkProver::from_params_and_assets(params_map, &dirs.assets);
+        log::info!("chain_prover: constructed chunk prover");
+
+        Mutex::new(LocalChunkProver {
+            params_map,
+            prover,
+            output_dir: dirs.output.clone(),
+        })
+    })
+}
+
+/// Returns the chunk proof (`None` when only mock-proving).
+pub fn prove_chunk(id: &str, traces: Vec<BlockTrace>) -> Result<Option<ChunkProofV2>, ProveError> {
+    // The real prover checks the chunk when generating its own witness block, so only the mock
+    // prover checks it first, and proves the same witness block.
+    #[cfg(not(any(feature = "inner-prove", feature = "chunk-prove")))]
+    let witness_block = check_chunk(id, &traces)?;
+
     let result = catch_unwind(AssertUnwindSafe(|| {
         #[cfg(not(feature = "chunk-prove"))]
         let proof = None::<ChunkProofV2>;
 
         #[cfg(feature = "chunk-prove")]
-        let proof = Some(prover::chunk_prove(
-            id,
-            prover::ChunkProvingTask::new(traces),
-        ));
+        let proof = {
+            use integration::prove::try_prove_and_verify_chunk;
+            use prover::ChunkProvingTask;
+
+            let chunk = ChunkProvingTask::new(traces);
+            let chunk_identifier = chunk.identifier();
+            // A chunk which panicked the prover doesn't stop the next ones.
+            let mut chunk_prover = chunk_prover()
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let LocalChunkProver {
+                params_map,
+                prover,
+                output_dir,
+            } = &mut *chunk_prover;
+            Some(try_prove_and_verify_chunk(
+                prover,
+                chunk,
+                &chunk_identifier,
+                params_map,
+                output_dir,
+            )?)
+        };
         #[cfg(not(any(feature = "inner-prove", feature = "chunk-prove")))]
-        mock_prove(id, traces);
+        mock_prove(id, &witness_block)?;
 
-        proof
+        Ok::<_, ProveError>(proof)
     }));
 
     match result {
-        Ok(proof) => {
+        Ok(Ok(proof)) => {
             log::info!("{id}: succeeded to prove chunk");
-            proof
+            Ok(proof)
+        }
+        Ok(Err(err)) => {
+            log::error!("{id}: failed to prove chunk:\n{err}");
+            Err(err)
         }
         Err(err) => {
-            let panic_err = if let Some(s) = err.downcast_ref::<String>() {
-                s.to_string()
-            } else if let Some(s) = err.downcast_ref::<&str>() {
-                s.to_string()
-            } else {
-                format!("unable to get panic info {err:?}")
-            };
+            let panic_err = panic_message(err);
             log::error!("{id}: failed to prove chunk:\n{panic_err:?}");
 
-            None
+            #[cfg(feature = "chunk-prove")]
+            let err = ProveError::RealProve(panic_err);
+            #[cfg(not(feature = "chunk-prove"))]
+            let err = ProveError::MockProve(panic_err);
+            Err(err)
         }
     }
 }
 
+/// Generates the witness block, and checks it fits in the circuits.
+#[cfg(not(any(feature = "inner-prove", feature = "chunk-prove")))]
+fn check_chunk(id: &str, traces: &[BlockTrace]) -> Result<Block, ProveError> {
+    let witness_block = catch_unwind(AssertUnwindSafe(|| {
+        chunk_trace_to_witness_block(traces.to_vec()).map_err(|e| e.to_string())
+    }))
+    .map_err(panic_message)
+    .and_then(|result| result)
+    .map_err(ProveError::WitnessGeneration)?;
+
+    let rows = calculate_row_usage_of_witness_block(&witness_block)
+        .map_err(|e| ProveError::WitnessGeneration(e.to_string()))?;
+    let row_usage = RowUsage::from_row_usage_details(rows).normalize();
+    if !row_usage.is_ok {
+        let bottleneck = row_usage
+            .row_usage_details
+            .iter()
+            .max_by_key(|r| r.row_number)
+            .map(|r| format!("{} rows of {}", r.row_number, r.name))
+            .unwrap_or_default();
+        return Err(ProveError::CccOverflow(format!(
+            "{id}: chunk overflows, normalized {bottleneck}"
+        )));
+    }
+
+    Ok(witness_block)
+}
+
 #[cfg(not(any(feature = "inner-prove", feature = "chunk-prove")))]
-fn mock_prove(id: &str, traces: Vec<prover::eth_types::l2_types::BlockTrace>) {
+fn mock_prove(id: &str, witness_block: &Block) -> Result<(), ProveError> {
     log::info!("{id}: mock-prove BEGIN");
 
-    integration::mock::mock_prove_target_circuit_chunk(traces)
-        .unwrap_or_else(|err| panic!("{id}: failed to mock-prove: {err}"));
+    integration::mock::mock_prove_witness_block(witness_block)
+        .map_err(|err| ProveError::MockProve(format!("{id}: failed to mock-prove: {err}")))?;
 
     log::info!("{id}: mock-prove END");
+    Ok(())
 }
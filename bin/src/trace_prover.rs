@@ -16,7 +16,7 @@ struct Args {
     #[clap(
         short,
         long = "trace",
-        default_value = "tests/extra_traces/batch_34700/chunk_1236462/block_4176564.json"
+        default_value = "tests/extra_traces/batch_73224/chunk_562585/block_4740248.json"
     )]
     trace_path: String,
 }
@@ -29,7 +29,9 @@ fn main() {
 
     let args = Args::parse();
 
-    let traces = load_chunk(&args.trace_path).1;
+    let traces = load_chunk(&args.trace_path)
+        .unwrap_or_else(|e| panic!("trace_prover: failed to load traces: {e:#}"))
+        .1;
     prover::eth_types::constants::set_scroll_block_constants_with_trace(&traces[0]);
     let chunk = ChunkProvingTask::new(traces);
     let params_map =
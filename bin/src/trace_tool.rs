@@ -0,0 +1,125 @@
+// Maintain a trace corpus, e.g. `integration/tests/extra_traces`:
+//   trace_tool gen-manifest --corpus integration/tests/extra_traces
+//   trace_tool check-manifest --corpus integration/tests/extra_traces [--linkage]
+//   trace_tool compress-traces --corpus integration/tests/extra_traces --format zstd
+
+use clap::{Parser, Subcommand};
+use integration::{
+    trace_file::{convert_trace_file, trace_files, Compression},
+    trace_manifest::{TraceManifest, MANIFEST_FILE},
+};
+use prover::init_env_and_log;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Root dir of the corpus, with `block_<n>.json` traces (or compressed) searched recursively.
+    #[clap(long, default_value = "integration/tests/extra_traces")]
+    corpus: String,
+    /// Manifest file. Defaults to `manifest.json` in the corpus root.
+    #[clap(long)]
+    manifest: Option<String>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write the manifest of all traces of the corpus.
+    GenManifest,
+    /// Check the traces of the corpus against the manifest.
+    CheckManifest {
+        /// Also check that the traces of each dir are of contiguous blocks, linked by parent
+        /// hash. Dirs sampling a chunk with gaps fail it.
+        #[clap(long)]
+        linkage: bool,
+    },
+    /// Convert all traces of the corpus in place, and update the manifest if there is one.
+    CompressTraces {
+        /// Compression to convert to: none, gzip or zstd.
+        #[clap(long, default_value = "zstd")]
+        format: Compression,
+    },
+}
+
+impl Args {
+    fn manifest_path(&self) -> PathBuf {
+        self.manifest
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| Path::new(&self.corpus).join(MANIFEST_FILE))
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    init_env_and_log("trace_tool");
+
+    let manifest_path = args.manifest_path();
+    match args.command {
+        Command::GenManifest => {
+            let manifest = TraceManifest::generate(&args.corpus)
+                .unwrap_or_else(|e| panic!("trace_tool: failed to generate manifest: {e:#}"));
+            manifest
+                .save(&manifest_path)
+                .unwrap_or_else(|e| panic!("trace_tool: {e:#}"));
+            log::info!(
+                "trace_tool: wrote {} entries to {}",
+                manifest.entries.len(),
+                manifest_path.display()
+            );
+        }
+        Command::CheckManifest { linkage } => {
+            let manifest =
+                TraceManifest::load(&manifest_path).unwrap_or_else(|e| panic!("trace_tool: {e:#}"));
+            manifest
+                .validate(&args.corpus)
+                .unwrap_or_else(|e| panic!("trace_tool: {e:#}"));
+            if linkage {
+                manifest
+                    .check_linkage()
+                    .unwrap_or_else(|e| panic!("trace_tool: {e:#}"));
+            }
+            log::info!(
+                "trace_tool: {} traces match {}",
+                manifest.entries.len(),
+                manifest_path.display()
+            );
+        }
+        Command::CompressTraces { format } => {
+            compress_traces(Path::new(&args.corpus), format);
+            if manifest_path.exists() {
+                let manifest = TraceManifest::generate(&args.corpus)
+                    .unwrap_or_else(|e| panic!("trace_tool: failed to update manifest: {e:#}"));
+                manifest
+                    .save(&manifest_path)
+                    .unwrap_or_else(|e| panic!("trace_tool: {e:#}"));
+                log::info!("trace_tool: updated {}", manifest_path.display());
+            }
+        }
+    }
+}
+
+fn compress_traces(corpus: &Path, format: Compression) {
+    let paths = trace_files(corpus).unwrap_or_else(|e| panic!("trace_tool: {e:#}"));
+    let file_size = |path: &Path| fs::metadata(path).map_or(0, |m| m.len());
+    let (mut size_before, mut size_after) = (0, 0);
+    for (i, path) in paths.iter().enumerate() {
+        size_before += file_size(path);
+        let new_path = convert_trace_file(path, format)
+            .unwrap_or_else(|e| panic!("trace_tool: failed to convert trace: {e:#}"));
+        size_after += file_size(&new_path);
+        log::debug!("trace_tool: {} -> {}", path.display(), new_path.display());
+        if (i + 1) % 100 == 0 {
+            log::info!("trace_tool: converted {}/{} traces", i + 1, paths.len());
+        }
+    }
+    log::info!(
+        "trace_tool: converted {} traces to {format:?}, {size_before} -> {size_after} bytes",
+        paths.len()
+    );
+}
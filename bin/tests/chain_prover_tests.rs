@@ -0,0 +1,198 @@
+// Run chain_prover end to end against fake l2geth and rollupscan servers, without network.
+
+use integration::{fake_rollupscan::FakeRollupscan, test_util::spawn_fake_l2geth};
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tokio::process::Command;
+
+const TRACE_DIR: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../integration/tests/extra_traces/batch_25"
+);
+const FIXTURE_DIR: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../integration/tests/test_data/rollupscan"
+);
+
+async fn spawn_fake_rollupscan() -> String {
+    let addr = FakeRollupscan::from_fixture_dir(FIXTURE_DIR)
+        .unwrap()
+        .spawn("127.0.0.1:0")
+        .await
+        .unwrap();
+    format!("http://{addr}/api")
+}
+
+/// Runs chain_prover with `CIRCUIT=<circuit>`, and returns its output dir.
+async fn run_chain_prover(test: &str, circuit: &str, args: &[&str]) -> PathBuf {
+    let output_dir = std::env::temp_dir().join(format!("chain_prover_tests_{test}"));
+    let _ = fs::remove_dir_all(&output_dir);
+
+    let status = Command::new(env!("CARGO_BIN_EXE_chain_prover"))
+        .args(args)
+        .env("CIRCUIT", circuit)
+        .env("OUTPUT_DIR", &output_dir)
+        .status()
+        .await
+        .unwrap();
+    assert!(status.success(), "chain_prover {args:?}: {status}");
+    assert!(
+        !output_dir.join("failure_report.json").exists(),
+        "chain_prover {args:?} reported failures in {}",
+        output_dir.display()
+    );
+
+    output_dir
+}
+
+fn read_jsonl(path: impl AsRef<Path>) -> Vec<Value> {
+    let path = path.as_ref();
+    fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {e}", path.display()))
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_block_prove() {
+    let l2geth_url = spawn_fake_l2geth(TRACE_DIR).await;
+
+    let output_dir = run_chain_prover(
+        "block_prove",
+        "ccc",
+        &[
+            "--l2geth-url",
+            &l2geth_url,
+            "block-prove",
+            "--begin",
+            "1341",
+            "--end",
+            "1370",
+        ],
+    )
+    .await;
+
+    // All 30 blocks fit in a single chunk.
+    let records = read_jsonl(output_dir.join("ccc_report.jsonl"));
+    let chunk_records = records
+        .iter()
+        .filter(|r| r["scope"] == "chunk")
+        .collect::<Vec<_>>();
+    assert_eq!(chunk_records.len(), 1, "{records:#?}");
+    assert_eq!(chunk_records[0]["begin_block"], 1341);
+    assert_eq!(chunk_records[0]["end_block"], 1370);
+}
+
+#[tokio::test]
+async fn test_batch_prove() {
+    let l2geth_url = spawn_fake_l2geth(TRACE_DIR).await;
+    let rollupscan_url = spawn_fake_rollupscan().await;
+
+    for circuit in ["none", "ccc"] {
+        let test = format!("batch_prove_{circuit}");
+        // Outside the output dir, which is cleared by each run.
+        let journal_dir = std::env::temp_dir().join(format!("chain_prover_tests_{test}_journal"));
+        let _ = fs::remove_dir_all(&journal_dir);
+        let journal = journal_dir.join("journal.jsonl");
+        let journal = journal.to_str().unwrap();
+        let args = [
+            "--l2geth-url",
+            &l2geth_url,
+            "--rollupscan-url",
+            &rollupscan_url,
+            "batch-prove",
+            "--begin",
+            "25",
+            "--end",
+            "25",
+            "--journal",
+            journal,
+        ];
+        let output_dir = run_chain_prover(&test, circuit, &args).await;
+
+        let entries = read_jsonl(journal);
+        let chunk_ids = entries
+            .iter()
+            .filter(|e| e["kind"] == "chunk")
+            .map(|e| e["chunk_id"].as_u64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(chunk_ids, [112, 113], "{entries:#?}");
+        let status = if circuit == "ccc" {
+            "ccc-only"
+        } else {
+            "skipped"
+        };
+        assert!(entries
+            .iter()
+            .filter(|e| e["kind"] == "chunk")
+            .all(|e| e["status"] == status));
+        let batch = entries.iter().find(|e| e["kind"] == "batch").unwrap();
+        assert_eq!(batch["batch_id"], 25);
+        assert_eq!(batch["status"], "skipped");
+        if circuit == "ccc" {
+            let records = read_jsonl(output_dir.join("ccc_report.jsonl"));
+            assert_eq!(records.iter().filter(|r| r["scope"] == "chunk").count(), 2);
+        }
+
+        // Everything is done, so resuming proves nothing again.
+        let mut resume_args = args.to_vec();
+        resume_args.push("--resume");
+        run_chain_prover(&test, circuit, &resume_args).await;
+        assert_eq!(read_jsonl(journal).len(), entries.len());
+
+        // Drop the entries of chunk 113 and the batch, as if the run was killed after chunk 112.
+        let kept = entries
+            .iter()
+            .filter(|e| e["kind"] == "chunk" && e["chunk_id"] == 112)
+            .map(|e| format!("{e}\n"))
+            .collect::<String>();
+        fs::write(journal, kept).unwrap();
+        run_chain_prover(&test, circuit, &resume_args).await;
+        let resumed = read_jsonl(journal);
+        let redone = resumed[1..]
+            .iter()
+            .map(|e| (e["kind"].as_str().unwrap(), e["chunk_id"].as_u64()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            redone,
+            [("chunk", Some(113)), ("batch", None)],
+            "{resumed:#?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_txtx_ccc() {
+    let l2geth_url = spawn_fake_l2geth(TRACE_DIR).await;
+
+    let output_dir = run_chain_prover(
+        "txtx_ccc",
+        "ccc",
+        &[
+            "--l2geth-url",
+            &l2geth_url,
+            "txtx-ccc",
+            "--begin",
+            "1341",
+            "--end",
+            "1345",
+        ],
+    )
+    .await;
+
+    // An optimal and a tx-by-tx (signer) record of each block.
+    let records = read_jsonl(output_dir.join("ccc_report.jsonl"));
+    assert_eq!(records.len(), 10, "{records:#?}");
+    for block_num in 1341..=1345 {
+        let modes = records
+            .iter()
+            .filter(|r| r["begin_block"] == block_num)
+            .map(|r| r["mode"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(modes, ["optimal", "signer"], "block {block_num}");
+    }
+}
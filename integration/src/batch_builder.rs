@@ -0,0 +1,322 @@
+//! Packs chunks into batches, like the batch proposer of the rollup relayer.
+
+use crate::{chunk_builder::ChunkStats, constants::N_BLOB_BYTES};
+use anyhow::{Context, Result};
+use prover::{BatchData, ChunkInfo, MAX_AGG_SNARKS};
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, path::Path};
+
+/// The limit which closed a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchLimit {
+    Chunks,
+    BlobBytes,
+    UncompressedBytes,
+    Timeout,
+    /// Closed by `BatchBuilder::finish`, at the end of the chunks.
+    Flush,
+}
+
+/// Accumulated stats of the chunks of a batch.
+#[derive(Debug, Clone, Default)]
+pub struct BatchStats {
+    pub chunks: usize,
+    pub blocks: usize,
+    pub first_block: u64,
+    pub last_block: u64,
+    /// Bytes of the blob, which are the uncompressed batch bytes when compression is skipped.
+    pub blob_bytes: usize,
+    pub uncompressed_bytes: usize,
+    pub first_timestamp: u64,
+    pub last_timestamp: u64,
+}
+
+impl BatchStats {
+    /// Ratio of the blob bytes to the blob capacity.
+    pub fn blob_utilization(&self) -> f64 {
+        self.blob_bytes as f64 / N_BLOB_BYTES as f64
+    }
+}
+
+/// Decides when a batch is closed.
+pub trait BatchPolicy {
+    /// Returns the limit exceeded by a batch of `stats`, if any. The last chunk of such a batch
+    /// is moved to the next batch.
+    fn exceeded_limit(&self, stats: &BatchStats) -> Option<BatchLimit>;
+
+    /// Returns the limit reached by a batch of `stats`, if any. Such a batch is closed with its
+    /// last chunk.
+    fn reached_limit(&self, _stats: &BatchStats) -> Option<BatchLimit> {
+        None
+    }
+
+    /// Whether to measure the blob by the uncompressed batch bytes, which is much faster.
+    fn skip_compression(&self) -> bool {
+        false
+    }
+}
+
+/// A `BatchPolicy` loadable from a JSON file. Fields missing from the file are the defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatchLimits {
+    pub max_chunks: usize,
+    /// Ratio of the blob capacity a batch may fill, e.g. 0.8 to close batches at 80% blob fill.
+    pub blob_utilization: f64,
+    /// Max seconds between the timestamps of the first and the last block.
+    pub timeout_secs: Option<u64>,
+    pub skip_compression: bool,
+}
+
+impl Default for BatchLimits {
+    fn default() -> Self {
+        Self {
+            max_chunks: MAX_AGG_SNARKS,
+            blob_utilization: 1.0,
+            timeout_secs: None,
+            skip_compression: false,
+        }
+    }
+}
+
+impl BatchLimits {
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = fs::read(path)
+            .with_context(|| format!("failed to read batch policy {}", path.display()))?;
+        serde_json::from_slice(&json)
+            .with_context(|| format!("failed to parse batch policy {}", path.display()))
+    }
+}
+
+impl BatchPolicy for BatchLimits {
+    fn exceeded_limit(&self, stats: &BatchStats) -> Option<BatchLimit> {
+        let uncompressed_bytes_limit = BatchData::<{ MAX_AGG_SNARKS }>::n_rows_data();
+
+        if stats.uncompressed_bytes > uncompressed_bytes_limit {
+            Some(BatchLimit::UncompressedBytes)
+        } else if stats.blob_bytes as f64 >= self.blob_utilization * N_BLOB_BYTES as f64 {
+            Some(BatchLimit::BlobBytes)
+        } else if self.timeout_secs.map_or(false, |timeout| {
+            stats.last_timestamp.saturating_sub(stats.first_timestamp) > timeout
+        }) {
+            Some(BatchLimit::Timeout)
+        } else {
+            None
+        }
+    }
+
+    fn reached_limit(&self, stats: &BatchStats) -> Option<BatchLimit> {
+        (stats.chunks >= self.max_chunks.min(MAX_AGG_SNARKS)).then_some(BatchLimit::Chunks)
+    }
+
+    fn skip_compression(&self) -> bool {
+        self.skip_compression
+    }
+}
+
+/// A closed batch.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    pub chunks: Vec<ChunkInfo>,
+    pub stats: BatchStats,
+    pub closed_by: BatchLimit,
+}
+
+/// A chunk which alone exceeds a size limit of the batch policy, so it fits in no batch. The
+/// sizes are of a batch of only this chunk.
+#[derive(Debug, Clone)]
+pub struct OversizeChunkError {
+    pub limit: BatchLimit,
+    pub blob_bytes: usize,
+    pub uncompressed_bytes: usize,
+}
+
+impl fmt::Display for OversizeChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "chunk alone exceeds the {:?} limit of a batch: blob bytes {} of {}, uncompressed bytes {} of {}",
+            self.limit,
+            self.blob_bytes,
+            N_BLOB_BYTES,
+            self.uncompressed_bytes,
+            BatchData::<{ MAX_AGG_SNARKS }>::n_rows_data(),
+        )
+    }
+}
+
+impl std::error::Error for OversizeChunkError {}
+
+/// Chunks of the batch being built.
+struct PendingBatch {
+    chunks: Vec<ChunkInfo>,
+    stats: BatchStats,
+    batch_data: BatchData<{ MAX_AGG_SNARKS }>,
+}
+
+impl PendingBatch {
+    fn new() -> Self {
+        Self {
+            chunks: Vec::new(),
+            stats: BatchStats::default(),
+            batch_data: BatchData {
+                num_valid_chunks: 0,
+                chunk_sizes: [0u32; MAX_AGG_SNARKS],
+                chunk_data: std::iter::repeat_with(Vec::new)
+                    .take(MAX_AGG_SNARKS)
+                    .collect::<Vec<_>>()
+                    .try_into()
+                    .unwrap(),
+            },
+        }
+    }
+
+    fn add_chunk(&mut self, chunk: ChunkInfo, chunk_stats: &ChunkStats, skip_compression: bool) {
+        let idx = self.batch_data.num_valid_chunks as usize;
+        self.batch_data.chunk_sizes[idx] = chunk.tx_bytes.len() as u32;
+        self.batch_data.chunk_data[idx] = chunk.tx_bytes.clone();
+        self.batch_data.num_valid_chunks += 1;
+        self.chunks.push(chunk);
+
+        if self.stats.chunks == 0 {
+            self.stats.first_block = chunk_stats.first_block;
+            self.stats.first_timestamp = chunk_stats.first_timestamp;
+        }
+        self.stats.last_block = chunk_stats.last_block;
+        self.stats.last_timestamp = chunk_stats.last_timestamp;
+        self.stats.chunks += 1;
+        self.stats.blocks += chunk_stats.blocks;
+        self.stats.uncompressed_bytes = self
+            .batch_data
+            .chunk_sizes
+            .iter()
+            .map(|s| *s as usize)
+            .sum();
+        let batch_bytes = self.batch_data.get_batch_data_bytes();
+        self.stats.blob_bytes = if skip_compression {
+            batch_bytes.len()
+        } else {
+            prover::get_blob_bytes(&batch_bytes).len()
+        };
+    }
+
+    /// Removes the last chunk, `prev_stats` being the stats before it was added.
+    fn pop_chunk(&mut self, prev_stats: BatchStats) -> ChunkInfo {
+        self.batch_data.num_valid_chunks -= 1;
+        let idx = self.batch_data.num_valid_chunks as usize;
+        self.batch_data.chunk_sizes[idx] = 0;
+        self.batch_data.chunk_data[idx] = Vec::new();
+        self.stats = prev_stats;
+        self.chunks.pop().unwrap()
+    }
+
+    fn into_batch(self, closed_by: BatchLimit) -> Batch {
+        Batch {
+            chunks: self.chunks,
+            stats: self.stats,
+            closed_by,
+        }
+    }
+}
+
+/// Same with production "batch proposer"
+pub struct BatchBuilder<P = BatchLimits> {
+    policy: P,
+    pending: PendingBatch,
+}
+
+impl<P: BatchPolicy> BatchBuilder<P> {
+    pub fn new(policy: P) -> Self {
+        Self {
+            policy,
+            pending: PendingBatch::new(),
+        }
+    }
+
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    /// Adds a chunk, and returns the batches closed by it, in order. `chunk_stats` are the stats
+    /// of the blocks of the chunk.
+    ///
+    /// A chunk which alone exceeds the blob or the uncompressed bytes limit is rejected, leaving
+    /// the pending chunks as they were. A chunk which alone exceeds another limit (e.g. the
+    /// timeout) is closed as a batch of its own, after the pending chunks.
+    pub fn add(
+        &mut self,
+        chunk: ChunkInfo,
+        chunk_stats: &ChunkStats,
+    ) -> Result<Vec<Batch>, OversizeChunkError> {
+        let skip_compression = self.policy.skip_compression();
+        let prev_stats = self.pending.stats.clone();
+        self.pending.add_chunk(chunk, chunk_stats, skip_compression);
+        log::debug!(
+            "BatchBuilder: checking chunk with len {}",
+            self.pending.chunks.len()
+        );
+
+        if let Some(limit) = self.policy.exceeded_limit(&self.pending.stats) {
+            if self.pending.chunks.len() == 1 {
+                if is_size_limit(limit) {
+                    let err = oversize_chunk(limit, &self.pending.stats);
+                    self.pending.pop_chunk(prev_stats);
+                    return Err(err);
+                }
+                log::warn!("BatchBuilder: chunk alone exceeds {limit:?}, closing it as a batch");
+                return Ok(vec![self.close(limit)]);
+            }
+
+            // Pop the last chunk, and check it fits in a batch of its own before emitting the
+            // prev chunks.
+            let chunk = self.pending.pop_chunk(prev_stats);
+            let mut alone = PendingBatch::new();
+            alone.add_chunk(chunk, chunk_stats, skip_compression);
+            let alone_limit = self.policy.exceeded_limit(&alone.stats);
+            if let Some(alone_limit) = alone_limit.filter(|limit| is_size_limit(*limit)) {
+                return Err(oversize_chunk(alone_limit, &alone.stats));
+            }
+            let mut batches = vec![std::mem::replace(&mut self.pending, alone).into_batch(limit)];
+            if let Some(alone_limit) = alone_limit {
+                log::warn!(
+                    "BatchBuilder: chunk alone exceeds {alone_limit:?}, closing it as a batch"
+                );
+                batches.push(self.close(alone_limit));
+            }
+            return Ok(batches);
+        }
+
+        // The batch data holds at most MAX_AGG_SNARKS chunks, whatever the policy.
+        let reached_limit = self.policy.reached_limit(&self.pending.stats).or_else(|| {
+            (self.pending.chunks.len() >= MAX_AGG_SNARKS).then_some(BatchLimit::Chunks)
+        });
+        Ok(reached_limit
+            .map(|limit| self.close(limit))
+            .into_iter()
+            .collect())
+    }
+
+    /// Closes the pending chunks as a batch, if any.
+    pub fn finish(&mut self) -> Option<Batch> {
+        (!self.pending.chunks.is_empty()).then(|| self.close(BatchLimit::Flush))
+    }
+
+    fn close(&mut self, closed_by: BatchLimit) -> Batch {
+        std::mem::replace(&mut self.pending, PendingBatch::new()).into_batch(closed_by)
+    }
+}
+
+/// Whether a chunk exceeding `limit` alone can't be put in any batch.
+fn is_size_limit(limit: BatchLimit) -> bool {
+    matches!(limit, BatchLimit::BlobBytes | BatchLimit::UncompressedBytes)
+}
+
+fn oversize_chunk(limit: BatchLimit, stats: &BatchStats) -> OversizeChunkError {
+    OversizeChunkError {
+        limit,
+        blob_bytes: stats.blob_bytes,
+        uncompressed_bytes: stats.uncompressed_bytes,
+    }
+}
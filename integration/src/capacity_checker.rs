@@ -1,16 +1,69 @@
+use crate::ccc_report::{CccRecord, CccReport, Scope};
+use anyhow::{anyhow, Result};
+use futures::{stream, StreamExt};
 use itertools::Itertools;
 use prover::{
     calculate_row_usage_of_witness_block, chunk_trace_to_witness_block,
     eth_types::l2_types::BlockTrace, zkevm_circuits::evm_circuit::ExecutionState,
     CircuitCapacityChecker, RowUsage, SubCircuitRowUsage,
 };
-use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    future::Future,
+    panic::{catch_unwind, AssertUnwindSafe},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, Mutex, Semaphore};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum CCCMode {
     Optimal,
+    #[serde(rename = "signer")]
     Siger,
     FollowerFull,
+    FollowerLight,
+}
+
+impl CCCMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CCCMode::Optimal => "optimal",
+            CCCMode::Siger => "signer",
+            CCCMode::FollowerFull => "follower-full",
+            CCCMode::FollowerLight => "follower-light",
+        }
+    }
+
+    // Tag in the logs of `pretty_print_row_usage`.
+    fn tag(&self) -> &'static str {
+        match self {
+            CCCMode::Optimal => "chunk-opt",
+            CCCMode::Siger => "chunk-signer",
+            CCCMode::FollowerFull => "chunk-f-f",
+            CCCMode::FollowerLight => "chunk-f-l",
+        }
+    }
+}
+
+impl FromStr for CCCMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            CCCMode::Optimal,
+            CCCMode::Siger,
+            CCCMode::FollowerFull,
+            CCCMode::FollowerLight,
+        ]
+        .into_iter()
+        .find(|mode| mode.as_str() == s)
+        .ok_or_else(|| anyhow!("unknown ccc mode {s:?}"))
+    }
 }
 
 pub fn prepare_circuit_capacity_checker() {
@@ -20,43 +73,59 @@ pub fn prepare_circuit_capacity_checker() {
     //debug_assert_eq!(mulmod_height, 18);
 }
 
-// Return average ccc time for each tx.
+/// Returns the chunk record of each mode, and the block records of modes estimating per block.
+/// Also returns how the other modes under-estimate the first one, when it's `Optimal`.
 pub fn run_circuit_capacity_checker(
     batch_id: u64,
     chunk_id: u64,
     block_traces: &[BlockTrace],
     modes: &[CCCMode],
-) -> Option<Duration> {
+    tolerance: &CccTolerance,
+) -> (CccReport, Vec<CccDiscrepancy>) {
+    let mut report = CccReport::new();
     let results = modes
         .iter()
         .map(|mode| {
-            (
+            let start_time = Instant::now();
+            let result = match mode {
+                CCCMode::Optimal => ccc_by_chunk(batch_id, chunk_id, block_traces),
+                CCCMode::Siger => ccc_as_signer(batch_id, chunk_id, block_traces, &mut report),
+                CCCMode::FollowerFull => {
+                    ccc_as_follower_full(batch_id, chunk_id, block_traces, &mut report)
+                }
+                CCCMode::FollowerLight => {
+                    ccc_as_follower_light(batch_id, chunk_id, block_traces, &mut report)
+                }
+            };
+            report.push(CccRecord::new(
+                Scope::Chunk,
                 *mode,
-                match mode {
-                    CCCMode::Optimal => ccc_by_chunk(batch_id, chunk_id, block_traces),
-                    CCCMode::Siger => ccc_as_signer(chunk_id, block_traces),
-                    CCCMode::FollowerFull => ccc_as_follower_full(chunk_id, block_traces),
-                },
-            )
+                batch_id,
+                chunk_id,
+                block_traces,
+                &result.0,
+                start_time.elapsed(),
+            ));
+            (*mode, result)
         })
         .collect_vec();
 
+    let mut discrepancies = vec![];
     if results.len() > 1 {
         for idx in 1..results.len() {
-            compare_ccc_results(
+            discrepancies.extend(compare_ccc_results(
                 chunk_id,
+                block_traces,
                 &results[0].1 .0,
                 results[0].0,
                 &results[idx].1 .0,
                 results[idx].0,
-            );
+                tolerance,
+            ));
         }
     }
 
-    results
-        .into_iter()
-        .find(|(mode, _)| *mode == CCCMode::Siger)
-        .map(|(_, (_, t))| t)
+    (report, discrepancies)
 }
 
 /// print analyze results
@@ -112,46 +181,66 @@ fn ccc_block_whole_block(
     checker.estimate_circuit_capacity(block.clone()).unwrap();
 }
 
+/// Estimates a block from its light trace, with a checker in light mode.
+fn ccc_block_light(checker: &mut CircuitCapacityChecker, _block_idx: usize, block: &BlockTrace) {
+    debug_assert!(checker.light_mode);
+    checker
+        .estimate_circuit_capacity(light_trace_of_block(block))
+        .unwrap();
+}
+
 fn ccc_block_tx_by_tx(checker: &mut CircuitCapacityChecker, block_idx: usize, block: &BlockTrace) {
     for tx_idx in 0..block.transactions.len() {
         log::info!("processing {}th block {}th tx", block_idx, tx_idx);
-        #[rustfmt::skip]
-        /*  
-        The capacity_checker is expected to be run inside sequencer, where we don't have the traces of blocks, instead we only have traces of tx.
-        For the "tx_trace":
-            transactions: 
-                the tx itself. For compatibility reasons, transactions is a vector of len 1 now.   
-            execution_results: 
-                tx execution trace. Similar with above, it is also of len 1 vevtor.   
-            storage_trace: 
-                storage_trace is prestate + siblings(or proofs) of touched storage_slots and accounts of this tx.
-        */
-
-        let tx_trace = BlockTrace {
-            transactions: vec![block.transactions[tx_idx].clone()],
-            execution_results: vec![block.execution_results[tx_idx].clone()],
-            storage_trace: block.tx_storage_trace[tx_idx].clone(),
-            chain_id: block.chain_id,
-            coinbase: block.coinbase.clone(),
-            codes: block.codes.clone(),
-            header: block.header.clone(),
-            start_l1_queue_index: block.start_l1_queue_index,
-            ..Default::default()
-        };
+        let tx_trace = tx_trace_of_block(block, tx_idx);
         log::debug!("calling estimate_circuit_capacity");
         let results = checker.estimate_circuit_capacity(tx_trace).unwrap();
         log::info!("after {}th block {}th tx: {:?}", block_idx, tx_idx, results);
     }
 }
 
-// Return row-usage and average ccc time for each tx.
+/// Extract the trace of the `tx_idx`th tx from a block trace (with `tx_storage_trace`).
+///
+/// The capacity_checker is expected to be run inside sequencer, where we don't have the traces of
+/// blocks, instead we only have traces of tx. For the "tx_trace":
+/// - transactions: the tx itself. For compatibility reasons, transactions is a vector of len 1 now.
+/// - execution_results: tx execution trace. Similar with above, it is also of len 1 vector.
+/// - storage_trace: prestate + siblings(or proofs) of touched storage_slots and accounts of this
+///   tx.
+pub fn tx_trace_of_block(block: &BlockTrace, tx_idx: usize) -> BlockTrace {
+    BlockTrace {
+        transactions: vec![block.transactions[tx_idx].clone()],
+        execution_results: vec![block.execution_results[tx_idx].clone()],
+        storage_trace: block.tx_storage_trace[tx_idx].clone(),
+        chain_id: block.chain_id,
+        coinbase: block.coinbase.clone(),
+        codes: block.codes.clone(),
+        header: block.header.clone(),
+        start_l1_queue_index: block.start_l1_queue_index,
+        ..Default::default()
+    }
+}
+
+/// Strip a block trace down to what a follower node has, i.e. without the per-tx
+/// `tx_storage_trace`. Without the per-tx storage proofs, the zktrie rows can't be estimated, so
+/// it's only estimated by a checker in light mode, which skips them.
+pub fn light_trace_of_block(block: &BlockTrace) -> BlockTrace {
+    BlockTrace {
+        tx_storage_trace: vec![],
+        ..block.clone()
+    }
+}
+
+// Return row-usage and average ccc time for each tx. Row usage of each block is added to `report`.
 fn get_ccc_result_of_chunk(
+    batch_id: u64,
     chunk_id: u64,
     blocks: &[BlockTrace],
-    by_block: bool, // by block instead of by tx
+    mode: CCCMode,
     norm: bool,
-    tag: &str,
+    report: &mut CccReport,
 ) -> (RowUsage, Duration) {
+    let tag = mode.tag();
     log::info!(
         "estimating circuit rows tx by tx, tx num {}",
         blocks
@@ -161,19 +250,25 @@ fn get_ccc_result_of_chunk(
     );
 
     let mut checker = CircuitCapacityChecker::new();
+    match mode {
+        CCCMode::FollowerLight => checker.light_mode = true,
+        CCCMode::FollowerFull => checker.light_mode = false,
+        _ => {}
+    }
 
-    let start_time = std::time::Instant::now();
+    let start_time = Instant::now();
 
     let mut tx_num = 0;
     let mut acc_row_usage_normalized = RowUsage::default();
     let mut acc_row_usage_raw = RowUsage::default();
     for (block_idx, block) in blocks.iter().enumerate() {
         checker.reset();
+        let block_start_time = Instant::now();
 
-        if by_block {
-            ccc_block_whole_block(&mut checker, block_idx, block);
-        } else {
-            ccc_block_tx_by_tx(&mut checker, block_idx, block);
+        match mode {
+            CCCMode::FollowerFull => ccc_block_whole_block(&mut checker, block_idx, block),
+            CCCMode::FollowerLight => ccc_block_light(&mut checker, block_idx, block),
+            _ => ccc_block_tx_by_tx(&mut checker, block_idx, block),
         }
 
         let block_result_raw = checker.get_acc_row_usage(false);
@@ -193,6 +288,15 @@ fn get_ccc_result_of_chunk(
             chunk_id,
             "inner",
         );
+        report.push(CccRecord::new(
+            Scope::Block,
+            mode,
+            batch_id,
+            chunk_id,
+            std::slice::from_ref(block),
+            &block_result_raw,
+            block_start_time.elapsed(),
+        ));
         acc_row_usage_raw.add(&block_result_raw);
         acc_row_usage_normalized.add(&block_result_raw.normalize());
 
@@ -200,7 +304,7 @@ fn get_ccc_result_of_chunk(
     }
     log::info!("capacity_checker test done");
     pretty_print_row_usage(&acc_row_usage_raw, blocks, chunk_id, tag);
-    let avg_ccc_time = start_time.elapsed().as_millis() / tx_num as u128;
+    let avg_ccc_time = start_time.elapsed().as_millis() / tx_num.max(1) as u128;
     log::info!("avg time each tx: {avg_ccc_time}ms, mode {tag}");
 
     (
@@ -222,13 +326,116 @@ fn get_ccc_result_by_whole_block(chunk_id: u64, blocks: &[BlockTrace]) -> RowUsa
     ccc_result
 }
 
-fn compare_ccc_results(
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Under-estimated within the tolerance.
+    Info,
+    /// Under-estimated beyond the tolerance, by at most `CccTolerance::error_ratio`.
+    Warning,
+    Error,
+}
+
+/// How many rows a mode may under-estimate `Optimal` by.
+///
+/// Parsed from e.g. `default=0,bytecode=1,error_ratio=0.01`, where keys other than `default`
+/// and `error_ratio` are sub-circuit names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CccTolerance {
+    /// Allowed under-estimated rows of sub-circuits not in `sub_circuits`.
+    pub default: usize,
+    /// Allowed under-estimated rows by sub-circuit name.
+    pub sub_circuits: BTreeMap<String, usize>,
+    /// Under-estimates beyond the tolerance are errors when they exceed this ratio of the
+    /// optimal rows, warnings otherwise.
+    pub error_ratio: f64,
+}
+
+impl Default for CccTolerance {
+    fn default() -> Self {
+        Self {
+            default: 0,
+            // FIXME the "+1", bytecode
+            sub_circuits: BTreeMap::from([("bytecode".to_string(), 1)]),
+            error_ratio: 0.0,
+        }
+    }
+}
+
+impl CccTolerance {
+    pub fn rows_of(&self, sub_circuit: &str) -> usize {
+        self.sub_circuits
+            .get(sub_circuit)
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    fn severity(&self, sub_circuit: &str, optimal_rows: usize, rows: usize) -> Option<Severity> {
+        let shortfall = optimal_rows.checked_sub(rows).filter(|n| *n > 0)?;
+        Some(if shortfall <= self.rows_of(sub_circuit) {
+            Severity::Info
+        } else if shortfall as f64 <= self.error_ratio * optimal_rows as f64 {
+            Severity::Warning
+        } else {
+            Severity::Error
+        })
+    }
+}
+
+impl FromStr for CccTolerance {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tolerance = Self::default();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expect `<sub-circuit>=<rows>`, got {pair:?}"))?;
+            match key.trim() {
+                "default" => tolerance.default = value.trim().parse()?,
+                "error_ratio" => tolerance.error_ratio = value.trim().parse()?,
+                name => {
+                    tolerance
+                        .sub_circuits
+                        .insert(name.to_string(), value.trim().parse()?);
+                }
+            }
+        }
+        Ok(tolerance)
+    }
+}
+
+/// A sub-circuit whose rows are under-estimated compared with `Optimal`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CccDiscrepancy {
+    pub chunk_id: u64,
+    pub begin_block: u64,
+    pub end_block: u64,
+    pub sub_circuit: String,
+    pub optimal_rows: usize,
+    pub mode: CCCMode,
+    pub rows: usize,
+    pub severity: Severity,
+}
+
+/// Logs the row usage of both modes, and returns the sub-circuits `rhs` under-estimates when
+/// `lhs` is `Optimal`.
+pub fn compare_ccc_results(
     chunk_id: u64,
+    block_traces: &[BlockTrace],
     lhs: &RowUsage,
     lhs_mode: CCCMode,
     rhs: &RowUsage,
     rhs_mode: CCCMode,
-) {
+    tolerance: &CccTolerance,
+) -> Vec<CccDiscrepancy> {
+    let block_num = |trace: Option<&BlockTrace>| {
+        trace
+            .and_then(|t| t.header.number)
+            .map(|n| n.as_u64())
+            .unwrap_or_default()
+    };
+    let mut discrepancies = vec![];
     for (l, r) in lhs
         .row_usage_details
         .iter()
@@ -243,11 +450,28 @@ fn compare_ccc_results(
             r.row_number,
             r.row_number as f64 / l.row_number as f64
         );
-        if lhs_mode == CCCMode::Optimal {
-            // FIXME the "+1", bytecode
-            assert!(r.row_number + 1 >= l.row_number);
+        // The bottleneck is implied by the sub-circuits.
+        if lhs_mode != CCCMode::Optimal || l.name.starts_with("bottleneck-") {
+            continue;
+        }
+        if let Some(severity) = tolerance.severity(&l.name, l.row_number, r.row_number) {
+            let discrepancy = CccDiscrepancy {
+                chunk_id,
+                begin_block: block_num(block_traces.first()),
+                end_block: block_num(block_traces.last()),
+                sub_circuit: l.name.clone(),
+                optimal_rows: l.row_number,
+                mode: rhs_mode,
+                rows: r.row_number,
+                severity,
+            };
+            if severity > Severity::Info {
+                log::warn!("chunk {chunk_id}: {discrepancy:?}");
+            }
+            discrepancies.push(discrepancy);
         }
     }
+    discrepancies
 }
 
 /// most accurate, optimal
@@ -256,25 +480,295 @@ pub fn ccc_by_chunk(
     chunk_id: u64,
     block_traces: &[BlockTrace],
 ) -> (RowUsage, Duration) {
+    try_ccc_by_chunk(batch_id, chunk_id, block_traces)
+        .unwrap_or_else(|e| panic!("ccc_by_chunk: batch-{batch_id} chunk-{chunk_id}: {e}"))
+}
+
+/// Same as `ccc_by_chunk`, but returns an error when the witness generation fails (or panics).
+pub fn try_ccc_by_chunk(
+    batch_id: u64,
+    chunk_id: u64,
+    block_traces: &[BlockTrace],
+) -> Result<(RowUsage, Duration)> {
     log::info!("ccc_by_chunk: run ccc for batch-{batch_id} chunk-{chunk_id}");
 
-    let start_time = std::time::Instant::now();
-    let witness_block = chunk_trace_to_witness_block(Vec::from(block_traces)).unwrap();
-    let rows = calculate_row_usage_of_witness_block(&witness_block).unwrap();
+    let start_time = Instant::now();
+    let (witness_block, rows) = catch_unwind(AssertUnwindSafe(|| -> Result<_> {
+        let witness_block = chunk_trace_to_witness_block(Vec::from(block_traces))
+            .map_err(|e| anyhow!("witness generation failed: {e}"))?;
+        let rows = calculate_row_usage_of_witness_block(&witness_block)
+            .map_err(|e| anyhow!("row usage calculation failed: {e}"))?;
+        Ok((witness_block, rows))
+    }))
+    .map_err(|e| anyhow!("ccc panicked: {}", panic_message(e)))??;
     let row_usage = RowUsage::from_row_usage_details(rows);
-    pretty_print_row_usage(&row_usage, block_traces, chunk_id, "chunk-opt");
+    pretty_print_row_usage(&row_usage, block_traces, chunk_id, CCCMode::Optimal.tag());
 
     let avg_ccc_time_per_tx = Duration::from_millis(
-        start_time.elapsed().as_millis() as u64 / witness_block.txs.len() as u64,
+        start_time.elapsed().as_millis() as u64 / witness_block.txs.len().max(1) as u64,
     );
 
-    (row_usage, avg_ccc_time_per_tx)
+    Ok((row_usage, avg_ccc_time_per_tx))
+}
+
+/// Message of a panic caught by `catch_unwind`.
+pub fn panic_message(err: Box<dyn Any + Send>) -> String {
+    if let Some(s) = err.downcast_ref::<String>() {
+        s.clone()
+    } else if let Some(s) = err.downcast_ref::<&str>() {
+        s.to_string()
+    } else {
+        format!("unable to get panic info {err:?}")
+    }
 }
 
-pub fn ccc_as_signer(chunk_id: u64, blocks: &[BlockTrace]) -> (RowUsage, Duration) {
-    get_ccc_result_of_chunk(chunk_id, blocks, false, false, "chunk-signer")
+pub fn ccc_as_signer(
+    batch_id: u64,
+    chunk_id: u64,
+    blocks: &[BlockTrace],
+    report: &mut CccReport,
+) -> (RowUsage, Duration) {
+    get_ccc_result_of_chunk(batch_id, chunk_id, blocks, CCCMode::Siger, false, report)
 }
 
-pub fn ccc_as_follower_full(chunk_id: u64, blocks: &[BlockTrace]) -> (RowUsage, Duration) {
-    get_ccc_result_of_chunk(chunk_id, blocks, true, false, "chunk-f-f")
+pub fn ccc_as_follower_full(
+    batch_id: u64,
+    chunk_id: u64,
+    blocks: &[BlockTrace],
+    report: &mut CccReport,
+) -> (RowUsage, Duration) {
+    get_ccc_result_of_chunk(
+        batch_id,
+        chunk_id,
+        blocks,
+        CCCMode::FollowerFull,
+        false,
+        report,
+    )
+}
+
+pub fn ccc_as_follower_light(
+    batch_id: u64,
+    chunk_id: u64,
+    blocks: &[BlockTrace],
+    report: &mut CccReport,
+) -> (RowUsage, Duration) {
+    get_ccc_result_of_chunk(
+        batch_id,
+        chunk_id,
+        blocks,
+        CCCMode::FollowerLight,
+        false,
+        report,
+    )
+}
+
+/// Configuration of `scan_blocks`.
+#[derive(Debug, Clone)]
+pub struct ScanConfig {
+    /// Number of blocks fetched concurrently.
+    pub fetch_concurrency: usize,
+    /// Number of blocks checked concurrently, each on a blocking thread.
+    pub workers: usize,
+    /// Number of fetched blocks waiting for a worker.
+    pub queue_size: usize,
+    pub modes: Vec<CCCMode>,
+    pub tolerance: CccTolerance,
+}
+
+#[derive(Debug)]
+pub enum ScanError {
+    Fetch(anyhow::Error),
+    /// The capacity checker panicked.
+    Check(String),
+}
+
+impl std::fmt::Display for ScanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanError::Fetch(e) => write!(f, "failed to fetch: {e}"),
+            ScanError::Check(e) => write!(f, "failed to check: {e}"),
+        }
+    }
+}
+
+/// CCC results of a block, checked as a chunk of its own.
+#[derive(Debug)]
+pub struct ScanResult {
+    pub block_num: u64,
+    pub tx_num: usize,
+    pub report: CccReport,
+    pub discrepancies: Vec<CccDiscrepancy>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ScanStats {
+    pub blocks: u64,
+    pub txs: u64,
+    pub failed: u64,
+    pub elapsed: Duration,
+}
+
+impl ScanStats {
+    pub fn blocks_per_sec(&self) -> f64 {
+        self.blocks as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn txs_per_sec(&self) -> f64 {
+        self.txs as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+// Log throughput every this many blocks.
+const SCAN_LOG_INTERVAL: u64 = 100;
+
+/// Runs the capacity checker on each block of `begin_block..=end_block`, pipelining `fetch`, with
+/// up to `config.fetch_concurrency` blocks in flight, with `config.workers` checkers. Results are
+/// passed to `on_result` in block order.
+///
+/// At most `config.queue_size + config.workers` blocks are fetched but not yet passed to
+/// `on_result`, so memory stays bounded when a block is slow to check.
+pub async fn scan_blocks<F, Fut>(
+    begin_block: u64,
+    end_block: u64,
+    config: &ScanConfig,
+    fetch: F,
+    mut on_result: impl FnMut(u64, Result<ScanResult, ScanError>),
+) -> ScanStats
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = Result<BlockTrace>>,
+{
+    let start_time = Instant::now();
+    let workers = config.workers.max(1);
+    let queue_size = config.queue_size.max(1);
+    let in_flight = Arc::new(Semaphore::new(queue_size + workers));
+    let (trace_tx, trace_rx) = mpsc::channel::<(u64, BlockTrace)>(queue_size);
+    let (result_tx, mut result_rx) = mpsc::channel(workers);
+
+    let trace_rx = Arc::new(Mutex::new(trace_rx));
+    for _ in 0..workers {
+        let trace_rx = trace_rx.clone();
+        let result_tx = result_tx.clone();
+        let modes = config.modes.clone();
+        let tolerance = config.tolerance.clone();
+        tokio::spawn(async move {
+            loop {
+                let Some((block_num, trace)) = trace_rx.lock().await.recv().await else {
+                    break;
+                };
+                let modes = modes.clone();
+                let tolerance = tolerance.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    let tx_num = trace.transactions.len();
+                    let (report, discrepancies) = run_circuit_capacity_checker(
+                        block_num,
+                        block_num,
+                        std::slice::from_ref(&trace),
+                        &modes,
+                        &tolerance,
+                    );
+                    ScanResult {
+                        block_num,
+                        tx_num,
+                        report,
+                        discrepancies,
+                    }
+                })
+                .await
+                .map_err(|e| match e.try_into_panic() {
+                    Ok(panic) => {
+                        ScanError::Check(format!("ccc panicked: {}", panic_message(panic)))
+                    }
+                    Err(e) => ScanError::Check(e.to_string()),
+                });
+                if result_tx.send((block_num, result)).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let fetch_blocks = {
+        let in_flight = in_flight.clone();
+        async move {
+            let fetch = &fetch;
+            // Blocks take a slot in order, so the next block to pass on is never left waiting.
+            let mut fetched = stream::iter(begin_block..=end_block)
+                .then(|block_num| {
+                    let in_flight = in_flight.clone();
+                    async move {
+                        in_flight.acquire().await.unwrap().forget();
+                        block_num
+                    }
+                })
+                .map(|block_num| async move { (block_num, fetch(block_num).await) })
+                .buffered(config.fetch_concurrency.max(1));
+            while let Some((block_num, fetched)) = fetched.next().await {
+                let sent = match fetched {
+                    Ok(trace) => trace_tx.send((block_num, trace)).await.is_ok(),
+                    Err(e) => result_tx
+                        .send((block_num, Err(ScanError::Fetch(e))))
+                        .await
+                        .is_ok(),
+                };
+                if !sent {
+                    break;
+                }
+            }
+            // Dropping the senders lets workers and the merging below finish.
+        }
+    };
+
+    let merge_results = async {
+        let mut stats = ScanStats::default();
+        let mut emit = |block_num: u64, result: Result<ScanResult, ScanError>| {
+            match &result {
+                Ok(result) => stats.txs += result.tx_num as u64,
+                Err(_) => stats.failed += 1,
+            }
+            stats.blocks += 1;
+            on_result(block_num, result);
+            in_flight.add_permits(1);
+
+            if stats.blocks % SCAN_LOG_INTERVAL == 0 {
+                stats.elapsed = start_time.elapsed();
+                log::info!(
+                    "ccc scan: block {block_num}, progress {:.1}%, {:.2} blocks/s, {:.2} txs/s",
+                    100.0 * stats.blocks as f64 / (end_block - begin_block + 1) as f64,
+                    stats.blocks_per_sec(),
+                    stats.txs_per_sec(),
+                );
+            }
+        };
+
+        let mut pending = BTreeMap::new();
+        let mut next_block = begin_block;
+        while let Some((block_num, result)) = result_rx.recv().await {
+            pending.insert(block_num, result);
+            while let Some(result) = pending.remove(&next_block) {
+                emit(next_block, result);
+                next_block += 1;
+            }
+        }
+        // Only left when fetching stopped early.
+        for (block_num, result) in pending {
+            emit(block_num, result);
+        }
+        stats
+    };
+
+    let ((), mut stats) = tokio::join!(fetch_blocks, merge_results);
+    stats.elapsed = start_time.elapsed();
+    log::info!(
+        "ccc scan: {} blocks ({} failed), {} txs in {:?}, {:.2} blocks/s, {:.2} txs/s",
+        stats.blocks,
+        stats.failed,
+        stats.txs,
+        stats.elapsed,
+        stats.blocks_per_sec(),
+        stats.txs_per_sec(),
+    );
+
+    stats
 }
@@ -0,0 +1,206 @@
+//! Machine-readable results of the circuit capacity checker, one record per block or chunk and
+//! `CCCMode`, written as JSON Lines or CSV.
+
+use crate::capacity_checker::CCCMode;
+use anyhow::{bail, Result};
+use prover::{eth_types::l2_types::BlockTrace, RowUsage};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Block,
+    Chunk,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubCircuitRows {
+    pub name: String,
+    pub rows: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CccRecord {
+    pub scope: Scope,
+    pub mode: CCCMode,
+    pub batch_id: u64,
+    pub chunk_id: u64,
+    pub begin_block: u64,
+    pub end_block: u64,
+    pub tx_num: usize,
+    pub gas_used: u64,
+    pub bottleneck: String,
+    pub bottleneck_rows: usize,
+    pub gas_per_row: f64,
+    pub elapsed_ms: u64,
+    pub avg_tx_ms: u64,
+    /// Raw (not normalized) rows of each sub-circuit.
+    pub rows: Vec<SubCircuitRows>,
+}
+
+impl CccRecord {
+    pub fn new(
+        scope: Scope,
+        mode: CCCMode,
+        batch_id: u64,
+        chunk_id: u64,
+        block_traces: &[BlockTrace],
+        row_usage: &RowUsage,
+        elapsed: Duration,
+    ) -> Self {
+        let block_num = |trace: Option<&BlockTrace>| {
+            trace
+                .and_then(|t| t.header.number)
+                .map(|n| n.as_u64())
+                .unwrap_or_default()
+        };
+        let tx_num: usize = block_traces.iter().map(|b| b.transactions.len()).sum();
+        let gas_used: u64 = block_traces
+            .iter()
+            .map(|b| b.header.gas_used.as_u64())
+            .sum();
+        let rows = row_usage
+            .row_usage_details
+            .iter()
+            .map(|r| SubCircuitRows {
+                name: r.name.clone(),
+                rows: r.row_number,
+            })
+            .collect::<Vec<_>>();
+        let bottleneck = rows
+            .iter()
+            .max_by_key(|r| r.rows)
+            .cloned()
+            .unwrap_or(SubCircuitRows {
+                name: String::new(),
+                rows: 0,
+            });
+
+        Self {
+            scope,
+            mode,
+            batch_id,
+            chunk_id,
+            begin_block: block_num(block_traces.first()),
+            end_block: block_num(block_traces.last()),
+            tx_num,
+            gas_used,
+            gas_per_row: gas_used as f64 / bottleneck.rows.max(1) as f64,
+            bottleneck: bottleneck.name,
+            bottleneck_rows: bottleneck.rows,
+            elapsed_ms: elapsed.as_millis() as u64,
+            avg_tx_ms: elapsed.as_millis() as u64 / tx_num.max(1) as u64,
+            rows,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CccReport {
+    records: Vec<CccRecord>,
+}
+
+impl CccReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, record: CccRecord) {
+        self.records.push(record);
+    }
+
+    pub fn extend(&mut self, other: CccReport) {
+        self.records.extend(other.records);
+    }
+
+    pub fn records(&self) -> &[CccRecord] {
+        &self.records
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Writes one JSON record per line.
+    pub fn write_jsonl(&self, mut writer: impl Write) -> Result<()> {
+        for record in &self.records {
+            serde_json::to_writer(&mut writer, record)?;
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes a CSV with a column per sub-circuit, named after the sub-circuits of the first
+    /// record. All records are expected to have the same sub-circuits.
+    pub fn write_csv(&self, mut writer: impl Write) -> Result<()> {
+        let sub_circuits = self
+            .records
+            .first()
+            .map(|r| r.rows.iter().map(|r| r.name.as_str()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        writeln!(
+            writer,
+            "scope,mode,batch_id,chunk_id,begin_block,end_block,tx_num,gas_used,bottleneck,bottleneck_rows,gas_per_row,elapsed_ms,avg_tx_ms{}",
+            sub_circuits.iter().map(|name| format!(",{name}")).collect::<String>()
+        )?;
+
+        for r in &self.records {
+            if r.rows
+                .iter()
+                .map(|r| r.name.as_str())
+                .ne(sub_circuits.iter().copied())
+            {
+                bail!(
+                    "sub-circuits of chunk {} ({:?}) differ from the first record",
+                    r.chunk_id,
+                    r.mode
+                );
+            }
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{},{:.4},{},{}{}",
+                match r.scope {
+                    Scope::Block => "block",
+                    Scope::Chunk => "chunk",
+                },
+                r.mode.as_str(),
+                r.batch_id,
+                r.chunk_id,
+                r.begin_block,
+                r.end_block,
+                r.tx_num,
+                r.gas_used,
+                r.bottleneck,
+                r.bottleneck_rows,
+                r.gas_per_row,
+                r.elapsed_ms,
+                r.avg_tx_ms,
+                r.rows
+                    .iter()
+                    .map(|r| format!(",{}", r.rows))
+                    .collect::<String>()
+            )?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes `<path_prefix>.jsonl` and `<path_prefix>.csv`.
+    pub fn save(&self, path_prefix: impl AsRef<Path>) -> Result<()> {
+        let path_prefix = path_prefix.as_ref();
+        self.write_jsonl(BufWriter::new(File::create(
+            path_prefix.with_extension("jsonl"),
+        )?))?;
+        self.write_csv(BufWriter::new(File::create(
+            path_prefix.with_extension("csv"),
+        )?))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+//! Packs blocks into chunks, like the chunk proposer of the rollup relayer.
+
+use crate::{capacity_checker::panic_message, chunk_info::tx_bytes_of_block};
+use anyhow::{anyhow, Context, Result};
+use prover::{eth_types::l2_types::BlockTrace, CircuitCapacityChecker, RowUsage};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::Path,
+};
+
+/// Size of a block context in the chunk payload.
+const BLOCK_CONTEXT_BYTES: usize = 60;
+
+/// The limit which closed a chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkLimit {
+    Blocks,
+    Txs,
+    Gas,
+    L1Messages,
+    PayloadBytes,
+    Rows,
+    Timeout,
+    /// Closed by `ChunkBuilder::finish`, at the end of the blocks.
+    Flush,
+}
+
+/// Accumulated stats of the blocks of a chunk.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkStats {
+    pub blocks: usize,
+    pub txs: usize,
+    pub gas: u64,
+    pub l1_messages: u64,
+    /// Block contexts and signed L2 txs, the chunk payload.
+    pub payload_bytes: usize,
+    /// Sum of the normalized row usage of each block.
+    pub row_usage: RowUsage,
+    pub first_block: u64,
+    pub last_block: u64,
+    pub first_timestamp: u64,
+    pub last_timestamp: u64,
+}
+
+impl ChunkStats {
+    pub fn add_block(&mut self, trace: &BlockTrace, row_usage: &RowUsage) {
+        let block_num = trace.header.number.unwrap_or_default().as_u64();
+        let timestamp = trace.header.timestamp.as_u64();
+        if self.blocks == 0 {
+            self.first_block = block_num;
+            self.first_timestamp = timestamp;
+        }
+        self.last_block = block_num;
+        self.last_timestamp = timestamp;
+        self.blocks += 1;
+        self.txs += trace.transactions.len();
+        self.gas += trace.header.gas_used.as_u64();
+        self.l1_messages += trace.num_l1_txs();
+        self.payload_bytes += BLOCK_CONTEXT_BYTES + tx_bytes_of_block(trace).len();
+        self.row_usage.add(row_usage);
+    }
+}
+
+/// Decides when a chunk is closed.
+pub trait ChunkPolicy {
+    /// Returns the limit exceeded by a chunk of `stats`, if any. The last block of such a chunk
+    /// is moved to the next chunk.
+    fn exceeded_limit(&self, stats: &ChunkStats) -> Option<ChunkLimit>;
+
+    /// Returns the limit reached by a chunk of `stats`, if any. Such a chunk is closed with its
+    /// last block.
+    fn reached_limit(&self, _stats: &ChunkStats) -> Option<ChunkLimit> {
+        None
+    }
+}
+
+/// A `ChunkPolicy` of optional limits, loadable from a JSON file. Limits missing from the file
+/// are the default ones, `null` limits are unlimited.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChunkLimits {
+    pub max_blocks: Option<usize>,
+    pub max_txs: Option<usize>,
+    pub max_gas: Option<u64>,
+    pub max_l1_messages: Option<u64>,
+    pub max_payload_bytes: Option<usize>,
+    /// Max normalized rows of the bottleneck sub-circuit. Chunks not fitting in the circuits are
+    /// closed anyway.
+    pub max_rows: Option<usize>,
+    /// Max seconds between the timestamps of the first and the last block.
+    pub timeout_secs: Option<u64>,
+}
+
+/// Only closes chunks which don't fit in the circuits, like chain_prover always did. Load the
+/// limits of a deployed proposer with `from_json_file` to reproduce its chunks.
+impl Default for ChunkLimits {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+/// The limits of the `chunk_proposer_config` of a rollup-relayer config file.
+#[derive(Debug, Deserialize)]
+struct ProposerConfig {
+    max_block_num_per_chunk: Option<usize>,
+    max_tx_num_per_chunk: Option<usize>,
+    max_l1_commit_calldata_size_per_chunk: Option<usize>,
+    max_row_consumption_per_chunk: Option<usize>,
+    chunk_timeout_sec: Option<u64>,
+}
+
+impl From<ProposerConfig> for ChunkLimits {
+    fn from(config: ProposerConfig) -> Self {
+        Self {
+            max_blocks: config.max_block_num_per_chunk,
+            max_txs: config.max_tx_num_per_chunk,
+            max_payload_bytes: config.max_l1_commit_calldata_size_per_chunk,
+            max_rows: config.max_row_consumption_per_chunk,
+            timeout_secs: config.chunk_timeout_sec,
+            ..Self::unlimited()
+        }
+    }
+}
+
+impl ChunkLimits {
+    /// Only closes chunks which don't fit in the circuits.
+    pub fn unlimited() -> Self {
+        Self {
+            max_blocks: None,
+            max_txs: None,
+            max_gas: None,
+            max_l1_messages: None,
+            max_payload_bytes: None,
+            max_rows: None,
+            timeout_secs: None,
+        }
+    }
+
+    /// Loads the limits from a JSON file, either of `ChunkLimits`, or the config file of a
+    /// deployed rollup-relayer, whose `chunk_proposer_config` (under `l2_config` or at the top
+    /// level) is used. The relayer's L1 commit gas limit has no counterpart, and is ignored.
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = fs::read(path)
+            .with_context(|| format!("failed to read chunk policy {}", path.display()))?;
+        let value: serde_json::Value = serde_json::from_slice(&json)
+            .with_context(|| format!("failed to parse chunk policy {}", path.display()))?;
+        let proposer_config = value
+            .pointer("/l2_config/chunk_proposer_config")
+            .or_else(|| value.get("chunk_proposer_config"));
+        match proposer_config {
+            Some(config) => serde_json::from_value::<ProposerConfig>(config.clone())
+                .map(Self::from)
+                .with_context(|| {
+                    format!("failed to parse chunk proposer config {}", path.display())
+                }),
+            None => serde_json::from_value(value)
+                .with_context(|| format!("failed to parse chunk policy {}", path.display())),
+        }
+    }
+}
+
+impl ChunkPolicy for ChunkLimits {
+    fn exceeded_limit(&self, stats: &ChunkStats) -> Option<ChunkLimit> {
+        let exceeds = |limit: Option<u64>, value: u64| limit.map_or(false, |limit| value > limit);
+        let as_u64 = |limit: Option<usize>| limit.map(|limit| limit as u64);
+
+        if exceeds(as_u64(self.max_blocks), stats.blocks as u64) {
+            Some(ChunkLimit::Blocks)
+        } else if !stats.row_usage.is_ok
+            || exceeds(as_u64(self.max_rows), stats.row_usage.row_number as u64)
+        {
+            Some(ChunkLimit::Rows)
+        } else if exceeds(as_u64(self.max_txs), stats.txs as u64) {
+            Some(ChunkLimit::Txs)
+        } else if exceeds(self.max_gas, stats.gas) {
+            Some(ChunkLimit::Gas)
+        } else if exceeds(self.max_l1_messages, stats.l1_messages) {
+            Some(ChunkLimit::L1Messages)
+        } else if exceeds(as_u64(self.max_payload_bytes), stats.payload_bytes as u64) {
+            Some(ChunkLimit::PayloadBytes)
+        } else if exceeds(
+            self.timeout_secs,
+            stats.last_timestamp.saturating_sub(stats.first_timestamp),
+        ) {
+            Some(ChunkLimit::Timeout)
+        } else {
+            None
+        }
+    }
+
+    fn reached_limit(&self, stats: &ChunkStats) -> Option<ChunkLimit> {
+        self.max_blocks
+            .filter(|max_blocks| stats.blocks >= *max_blocks)
+            .map(|_| ChunkLimit::Blocks)
+    }
+}
+
+/// A closed chunk.
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub blocks: Vec<BlockTrace>,
+    pub stats: ChunkStats,
+    pub closed_by: ChunkLimit,
+}
+
+/// Same with production "chunk proposer"
+pub struct ChunkBuilder<P = ChunkLimits> {
+    policy: P,
+    traces: Vec<BlockTrace>,
+    stats: ChunkStats,
+}
+
+impl<P: ChunkPolicy> ChunkBuilder<P> {
+    pub fn new(policy: P) -> Self {
+        Self {
+            policy,
+            traces: Vec::new(),
+            stats: ChunkStats::default(),
+        }
+    }
+
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    /// Adds a block, and returns the chunk closed by it, if any. A block the capacity checker
+    /// fails on (or panics on) is an error, and is not added.
+    pub fn add(&mut self, trace: BlockTrace) -> Result<Option<Chunk>> {
+        let row_usage = catch_unwind(AssertUnwindSafe(|| {
+            let mut checker = CircuitCapacityChecker::new();
+            checker.estimate_circuit_capacity(trace.clone())
+        }))
+        .map_err(|e| anyhow!("ccc panicked: {}", panic_message(e)))?
+        .map_err(|e| anyhow!("ccc failed: {e}"))
+        .with_context(|| format!("failed to add block {:?}", trace.header.number))?;
+        let mut stats = self.stats.clone();
+        stats.add_block(&trace, &row_usage);
+
+        if let Some(limit) = self.policy.exceeded_limit(&stats) {
+            if !self.traces.is_empty() {
+                // build a chunk with PREV traces
+                let chunk = self.close(limit);
+                self.add_block(trace, &row_usage);
+                return Ok(Some(chunk));
+            }
+            log::warn!(
+                "ChunkBuilder: block {:?} alone exceeds {limit:?}, closing it as a chunk",
+                trace.header.number
+            );
+            self.add_block(trace, &row_usage);
+            return Ok(Some(self.close(limit)));
+        }
+
+        self.traces.push(trace);
+        self.stats = stats;
+        Ok(self
+            .policy
+            .reached_limit(&self.stats)
+            .map(|limit| self.close(limit)))
+    }
+
+    /// Closes the pending blocks as a chunk, if any.
+    pub fn finish(&mut self) -> Option<Chunk> {
+        (!self.traces.is_empty()).then(|| self.close(ChunkLimit::Flush))
+    }
+
+    fn add_block(&mut self, trace: BlockTrace, row_usage: &RowUsage) {
+        self.stats.add_block(&trace, row_usage);
+        self.traces.push(trace);
+    }
+
+    fn close(&mut self, closed_by: ChunkLimit) -> Chunk {
+        Chunk {
+            blocks: std::mem::take(&mut self.traces),
+            stats: std::mem::take(&mut self.stats),
+            closed_by,
+        }
+    }
+}
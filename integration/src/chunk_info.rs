@@ -0,0 +1,104 @@
+//! `ChunkInfo` computed directly from block traces, which is much faster than building the
+//! witness block only to call `ChunkInfo::from_witness_block`.
+
+use ethers_core::utils::keccak256;
+use prover::{
+    eth_types::{l2_types::BlockTrace, H256, U64},
+    ChunkInfo,
+};
+
+/// Tx type of L1 messages, which are not part of the chunk payload.
+pub const L1_MESSAGE_TX_TYPE: u8 = 0x7e;
+
+/// Returns the same `ChunkInfo` as `ChunkInfo::from_witness_block` of the witness block built
+/// from `block_traces`.
+pub fn chunk_info_from_block_traces(block_traces: &[BlockTrace]) -> ChunkInfo {
+    let (first, last) = match block_traces {
+        [first, .., last] => (first, last),
+        [block] => (block, block),
+        [] => panic!("chunk_info_from_block_traces: empty chunk"),
+    };
+
+    ChunkInfo {
+        chain_id: first.chain_id,
+        prev_state_root: first.storage_trace.root_before,
+        post_state_root: last.header.state_root,
+        withdraw_root: last.withdraw_trie_root,
+        data_hash: data_hash(block_traces),
+        tx_bytes: block_traces.iter().flat_map(tx_bytes_of_block).collect(),
+        is_padding: false,
+    }
+}
+
+/// Keccak of the block contexts, followed by the hashes of the L1 messages.
+fn data_hash(block_traces: &[BlockTrace]) -> H256 {
+    // Skipped L1 messages are popped from the queue too, so count them by queue index (the
+    // nonce of an L1 message).
+    let mut total_l1_popped = block_traces[0].start_l1_queue_index;
+    let mut data_bytes = vec![];
+    for trace in block_traces {
+        let num_l2_txs = trace
+            .transactions
+            .iter()
+            .filter(|tx| tx.type_ != L1_MESSAGE_TX_TYPE)
+            .count() as u64;
+        let num_l1_msgs = trace
+            .transactions
+            .iter()
+            .filter(|tx| tx.type_ == L1_MESSAGE_TX_TYPE)
+            .map(|tx| tx.nonce)
+            .max()
+            .map_or(0, |max_queue_index| max_queue_index - total_l1_popped + 1);
+        total_l1_popped += num_l1_msgs;
+
+        let mut base_fee = [0u8; 32];
+        trace
+            .header
+            .base_fee_per_gas
+            .unwrap_or_default()
+            .to_big_endian(&mut base_fee);
+        data_bytes.extend(
+            trace
+                .header
+                .number
+                .unwrap_or_default()
+                .as_u64()
+                .to_be_bytes(),
+        );
+        data_bytes.extend(trace.header.timestamp.as_u64().to_be_bytes());
+        data_bytes.extend(base_fee);
+        data_bytes.extend(trace.header.gas_limit.as_u64().to_be_bytes());
+        data_bytes.extend(((num_l2_txs + num_l1_msgs) as u16).to_be_bytes());
+    }
+    for trace in block_traces {
+        data_bytes.extend(
+            trace
+                .transactions
+                .iter()
+                .filter(|tx| tx.type_ == L1_MESSAGE_TX_TYPE)
+                .flat_map(|tx| tx.tx_hash.to_fixed_bytes()),
+        );
+    }
+
+    H256(keccak256(data_bytes))
+}
+
+/// Signed RLP of the L2 txs of a block, which is the payload of the block in a chunk.
+pub fn tx_bytes_of_block(trace: &BlockTrace) -> Vec<u8> {
+    trace
+        .transactions
+        .iter()
+        .enumerate()
+        .filter(|(_, tx)| tx.type_ != L1_MESSAGE_TX_TYPE)
+        .flat_map(|(i, tx)| {
+            tx.to_eth_tx(
+                trace.header.hash,
+                trace.header.number,
+                Some(U64::from(i)),
+                trace.header.base_fee_per_gas,
+            )
+            .rlp()
+            .to_vec()
+        })
+        .collect()
+}
@@ -0,0 +1,157 @@
+//! A fake l2geth, serving the JSON-RPC methods used by `l2geth::Client` from a trace dir.
+
+use crate::{
+    capacity_checker::tx_trace_of_block,
+    http::{self, Request, Response},
+    trace_file::{block_num_of_file, read_block_trace},
+};
+use anyhow::{anyhow, bail, Result};
+use prover::eth_types::l2_types::BlockTrace;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+pub struct FakeL2geth {
+    // block number -> trace file
+    traces: BTreeMap<u64, PathBuf>,
+}
+
+impl FakeL2geth {
+    /// Indexes all trace files (`block_<n>.json`, or compressed) under `trace_dir` recursively,
+    /// e.g. `tests/extra_traces/batch_*/chunk_*/block_*.json`.
+    pub fn from_trace_dir(trace_dir: impl AsRef<Path>) -> Result<Self> {
+        let mut traces = BTreeMap::new();
+        index_trace_dir(trace_dir.as_ref(), &mut traces)?;
+        if traces.is_empty() {
+            bail!("no block traces under {}", trace_dir.as_ref().display());
+        }
+        log::info!(
+            "fake_l2geth: serving {} blocks, {} to {}",
+            traces.len(),
+            traces.first_key_value().unwrap().0,
+            traces.last_key_value().unwrap().0,
+        );
+
+        Ok(Self { traces })
+    }
+
+    pub fn block_numbers(&self) -> impl Iterator<Item = u64> + '_ {
+        self.traces.keys().copied()
+    }
+
+    /// Serves JSON-RPC on `addr` in a background task, returns the bound address.
+    pub async fn spawn(self, addr: &str) -> Result<SocketAddr> {
+        http::spawn(addr, move |req| self.handle_http(req)).await
+    }
+
+    fn handle_http(&self, req: Request) -> Response {
+        log::debug!("fake_l2geth: {} {}", req.method, req.target);
+        if req.method != "POST" {
+            return Response::not_found();
+        }
+        let resp = match serde_json::from_slice::<Value>(&req.body) {
+            Ok(Value::Array(reqs)) => Value::Array(reqs.iter().map(|r| self.handle(r)).collect()),
+            Ok(req) => self.handle(&req),
+            Err(e) => rpc_error(Value::Null, -32700, &format!("parse error: {e}")),
+        };
+        Response::json(&resp)
+    }
+
+    /// Handles a single JSON-RPC request.
+    pub fn handle(&self, req: &Value) -> Value {
+        let id = req.get("id").cloned().unwrap_or(Value::Null);
+        let method = req
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let params = req.get("params").cloned().unwrap_or(Value::Null);
+        log::debug!("fake_l2geth: {method} {params}");
+
+        let result = match method {
+            "eth_blockNumber" => Ok(json!(format!("{:#x}", self.latest_block_number()))),
+            "eth_chainId" => self
+                .load_trace(self.latest_block_number())
+                .map(|trace| json!(format!("{:#x}", trace.chain_id))),
+            "scroll_getBlockTraceByNumberOrHash" => self
+                .block_num_of_params(&params)
+                .and_then(|block_num| self.load_trace(block_num))
+                .and_then(|trace| serde_json::to_value(trace).map_err(Into::into)),
+            "scroll_getTxByTxBlockTrace" => self
+                .block_num_of_params(&params)
+                .and_then(|block_num| self.load_trace(block_num))
+                .and_then(|trace| {
+                    if trace.tx_storage_trace.len() != trace.transactions.len() {
+                        bail!("trace has no tx_storage_trace");
+                    }
+                    let tx_traces = (0..trace.transactions.len())
+                        .map(|tx_idx| tx_trace_of_block(&trace, tx_idx))
+                        .collect::<Vec<_>>();
+                    Ok(serde_json::to_value(tx_traces)?)
+                }),
+            _ => return rpc_error(id, -32601, &format!("method {method} not found")),
+        };
+
+        match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(e) => rpc_error(id, -32000, &e.to_string()),
+        }
+    }
+
+    fn latest_block_number(&self) -> u64 {
+        *self.traces.last_key_value().unwrap().0
+    }
+
+    fn block_num_of_params(&self, params: &Value) -> Result<u64> {
+        let block = params
+            .get(0)
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("missing block number param"))?;
+        match block {
+            "latest" => Ok(self.latest_block_number()),
+            _ => {
+                let hex = block
+                    .strip_prefix("0x")
+                    .ok_or_else(|| anyhow!("block number {block} is not hex"))?;
+                if hex.len() == 64 {
+                    bail!("lookup by block hash is not supported");
+                }
+                Ok(u64::from_str_radix(hex, 16)?)
+            }
+        }
+    }
+
+    fn load_trace(&self, block_num: u64) -> Result<BlockTrace> {
+        let path = self
+            .traces
+            .get(&block_num)
+            .ok_or_else(|| anyhow!("block {block_num} not found"))?;
+        read_block_trace(path)
+    }
+}
+
+fn index_trace_dir(dir: &Path, traces: &mut BTreeMap<u64, PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            index_trace_dir(&path, traces)?;
+            continue;
+        }
+        if let Some(block_num) = block_num_of_file(&path) {
+            if let Some(prev) = traces.insert(block_num, path.clone()) {
+                log::warn!(
+                    "fake_l2geth: block {block_num} found in both {} and {}, using the latter",
+                    prev.display(),
+                    path.display()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn rpc_error(id: Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
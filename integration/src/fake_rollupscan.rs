@@ -0,0 +1,94 @@
+//! A fake rollupscan, serving the API used by `rollupscan::Client` from a fixture dir.
+
+use crate::{
+    http::{self, Request, Response},
+    rollupscan::{BatchInfo, BatchResponse, ChunkInfo, ChunksResponse, LastBatchIndexes},
+};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, net::SocketAddr, path::Path};
+
+/// Content of a `batch_<n>.json` fixture.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BatchFixture {
+    pub batch: BatchInfo,
+    pub chunks: Vec<ChunkInfo>,
+}
+
+pub struct FakeRollupscan {
+    batches: BTreeMap<i64, BatchFixture>,
+}
+
+impl FakeRollupscan {
+    /// Loads all `batch_<n>.json` fixtures in `fixture_dir`.
+    pub fn from_fixture_dir(fixture_dir: impl AsRef<Path>) -> Result<Self> {
+        let mut batches = BTreeMap::new();
+        for path in crate::test_util::read_dir(&fixture_dir)? {
+            let is_fixture = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, |name| {
+                    name.starts_with("batch_") && name.ends_with(".json")
+                });
+            if !is_fixture {
+                continue;
+            }
+            let fixture: BatchFixture = serde_json::from_slice(&std::fs::read(&path)?)
+                .with_context(|| format!("failed to parse fixture {}", path.display()))?;
+            batches.insert(fixture.batch.index, fixture);
+        }
+        if batches.is_empty() {
+            bail!("no batch fixtures in {}", fixture_dir.as_ref().display());
+        }
+
+        Ok(Self { batches })
+    }
+
+    /// Serves the API on `addr` in a background task, returns the bound address.
+    /// The API base URL is `http://<addr>/api`.
+    pub async fn spawn(self, addr: &str) -> Result<SocketAddr> {
+        http::spawn(addr, move |req| self.handle_http(req)).await
+    }
+
+    fn handle_http(&self, req: Request) -> Response {
+        log::debug!("fake_rollupscan: {} {}", req.method, req.target);
+        if req.method != "GET" {
+            return Response::not_found();
+        }
+        let index = |key: &str| {
+            req.query_param(key)
+                .and_then(|index| index.parse::<i64>().ok())
+        };
+
+        match req.path() {
+            "/api/chunks" => match index("batch_index") {
+                Some(batch_index) => Response::json(&serde_json::json!(ChunksResponse {
+                    batch_index,
+                    chunks: self.batches.get(&batch_index).map(|b| b.chunks.clone()),
+                })),
+                None => Response::bad_request(),
+            },
+            "/api/batch" => match index("index") {
+                Some(batch_index) => Response::json(&serde_json::json!(BatchResponse {
+                    batch: self.batches.get(&batch_index).map(|b| b.batch.clone()),
+                })),
+                None => Response::bad_request(),
+            },
+            "/api/last_batch_indexes" => {
+                let latest = |is_counted: fn(&BatchInfo) -> bool| {
+                    self.batches
+                        .values()
+                        .rev()
+                        .find(|b| is_counted(&b.batch))
+                        .map_or(0, |b| b.batch.index)
+                };
+                Response::json(&serde_json::json!(LastBatchIndexes {
+                    all_index: latest(|_| true),
+                    committed_index: latest(BatchInfo::is_committed),
+                    finalized_index: latest(BatchInfo::is_finalized),
+                }))
+            }
+            _ => Response::not_found(),
+        }
+    }
+}
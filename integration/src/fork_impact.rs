@@ -0,0 +1,164 @@
+//! Impact of a hard fork on the circuit capacity: each block is checked as traced natively and
+//! as traced with the fork enabled by a chain config override.
+
+use crate::capacity_checker::try_ccc_by_chunk;
+use anyhow::Result;
+use prover::{eth_types::l2_types::BlockTrace, RowUsage};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, io::Write};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RowDelta {
+    pub name: String,
+    pub native: usize,
+    pub overridden: usize,
+    pub delta: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForkImpactRecord {
+    pub block_num: u64,
+    pub tx_num: usize,
+    /// Gas used by the txs, as executed by the trace.
+    pub native_gas: u64,
+    pub overridden_gas: u64,
+    pub gas_delta: i64,
+    pub native_error: Option<String>,
+    pub overridden_error: Option<String>,
+    /// Raw (not normalized) rows of each sub-circuit, empty when either CCC failed.
+    pub row_deltas: Vec<RowDelta>,
+}
+
+impl ForkImpactRecord {
+    /// Runs CCC on the native and the overridden trace of a block, and compares the results.
+    pub fn new(block_num: u64, native: &BlockTrace, overridden: &BlockTrace) -> Self {
+        let check = |trace: &BlockTrace| -> Result<RowUsage, String> {
+            try_ccc_by_chunk(block_num, block_num, std::slice::from_ref(trace))
+                .map(|(row_usage, _)| row_usage)
+                .map_err(|e| format!("{e:#}"))
+        };
+        let native_rows = check(native);
+        let overridden_rows = check(overridden);
+
+        let row_deltas = match (&native_rows, &overridden_rows) {
+            (Ok(native), Ok(overridden)) => row_deltas(native, overridden),
+            _ => vec![],
+        };
+        let native_gas = execution_gas(native);
+        let overridden_gas = execution_gas(overridden);
+
+        Self {
+            block_num,
+            tx_num: native.transactions.len(),
+            native_gas,
+            overridden_gas,
+            gas_delta: overridden_gas as i64 - native_gas as i64,
+            native_error: native_rows.err(),
+            overridden_error: overridden_rows.err(),
+            row_deltas,
+        }
+    }
+
+    /// Whether the witness generation only fails under the overridden rules.
+    pub fn is_regression(&self) -> bool {
+        self.native_error.is_none() && self.overridden_error.is_some()
+    }
+}
+
+fn execution_gas(trace: &BlockTrace) -> u64 {
+    trace.execution_results.iter().map(|r| r.gas).sum()
+}
+
+fn row_deltas(native: &RowUsage, overridden: &RowUsage) -> Vec<RowDelta> {
+    let overridden_rows: BTreeMap<_, _> = overridden
+        .row_usage_details
+        .iter()
+        .map(|r| (r.name.as_str(), r.row_number))
+        .collect();
+    native
+        .row_usage_details
+        .iter()
+        .map(|r| {
+            let overridden = overridden_rows.get(r.name.as_str()).copied().unwrap_or(0);
+            RowDelta {
+                name: r.name.clone(),
+                native: r.row_number,
+                overridden,
+                delta: overridden as i64 - r.row_number as i64,
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ForkImpactReport {
+    records: Vec<ForkImpactRecord>,
+}
+
+impl ForkImpactReport {
+    pub fn push(&mut self, record: ForkImpactRecord) {
+        self.records.push(record);
+    }
+
+    pub fn records(&self) -> &[ForkImpactRecord] {
+        &self.records
+    }
+
+    /// Writes one JSON record per line.
+    pub fn write_jsonl(&self, mut writer: impl Write) -> Result<()> {
+        for record in &self.records {
+            serde_json::to_writer(&mut writer, record)?;
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Logs the total and the max row delta of each sub-circuit, and the blocks failing under
+    /// the overridden rules.
+    pub fn log_summary(&self) {
+        let compared = self
+            .records
+            .iter()
+            .filter(|r| !r.row_deltas.is_empty())
+            .count();
+        let gas_delta: i64 = self.records.iter().map(|r| r.gas_delta).sum();
+        log::info!(
+            "fork impact: {} blocks, {compared} compared, gas delta {gas_delta}",
+            self.records.len()
+        );
+
+        // name -> (total delta, max delta, block of max delta)
+        let mut sub_circuits: BTreeMap<&str, (i64, i64, u64)> = BTreeMap::new();
+        for record in &self.records {
+            for d in &record.row_deltas {
+                let entry = sub_circuits
+                    .entry(d.name.as_str())
+                    .or_insert((0, i64::MIN, 0));
+                entry.0 += d.delta;
+                if d.delta > entry.1 {
+                    entry.1 = d.delta;
+                    entry.2 = record.block_num;
+                }
+            }
+        }
+        for (name, (total, max, block_num)) in sub_circuits {
+            log::info!(
+                "fork impact: {name}: total row delta {total}, max {max} (block {block_num})"
+            );
+        }
+
+        for record in self.records.iter().filter(|r| r.overridden_error.is_some()) {
+            log::warn!(
+                "fork impact: block {} fails under the override{}: {}",
+                record.block_num,
+                if record.is_regression() {
+                    ""
+                } else {
+                    " (and natively)"
+                },
+                record.overridden_error.as_deref().unwrap_or_default(),
+            );
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! A tiny HTTP/1.1 server, just enough to stand in for l2geth and rollupscan in tests.
+//! Every connection serves a single request and is then closed.
+
+use anyhow::{bail, Context, Result};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+#[derive(Debug)]
+pub(crate) struct Request {
+    pub method: String,
+    /// Path with query, e.g. `/api/chunks?batch_index=1`.
+    pub target: String,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or_default()
+    }
+
+    pub fn query_param(&self, key: &str) -> Option<&str> {
+        let (_, query) = self.target.split_once('?')?;
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json(body: &serde_json::Value) -> Self {
+        Self {
+            status: 200,
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    pub fn bad_request() -> Self {
+        Self {
+            status: 400,
+            body: b"bad request".to_vec(),
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self {
+            status: 404,
+            body: b"not found".to_vec(),
+        }
+    }
+}
+
+/// Binds `addr` and serves requests with `handler` in a background task.
+/// Returns the bound address, which is useful when binding port 0.
+pub(crate) async fn spawn<H>(addr: &str, handler: H) -> Result<SocketAddr>
+where
+    H: Fn(Request) -> Response + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind {addr}"))?;
+    let local_addr = listener.local_addr()?;
+    let handler = Arc::new(handler);
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    log::error!("http server {local_addr}: failed to accept: {e}");
+                    continue;
+                }
+            };
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, handler.as_ref()).await {
+                    log::warn!("http server {local_addr}: failed to serve {peer}: {e}");
+                }
+            });
+        }
+    });
+
+    Ok(local_addr)
+}
+
+async fn handle_connection<H>(stream: TcpStream, handler: &H) -> Result<()>
+where
+    H: Fn(Request) -> Response + Send + Sync,
+{
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        bail!("malformed request line {request_line:?}");
+    };
+    let (method, target) = (method.to_string(), target.to_string());
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 {
+            bail!("connection closed before end of headers");
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse()?;
+            }
+        }
+    }
+
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).await?;
+
+    let response = handler(Request {
+        method,
+        target,
+        body,
+    });
+
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        _ => "Internal Server Error",
+    };
+    let head = format!(
+        "HTTP/1.1 {} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.body.len(),
+    );
+    let mut stream = reader.into_inner();
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
@@ -1,11 +1,161 @@
-use anyhow::Result;
-use ethers_providers::{Http, Middleware, Provider};
-use prover::eth_types::l2_types::BlockTrace;
-use serde::Serialize;
+use crate::{trace_cache::TraceCache, trace_request::TraceRequest};
+use anyhow::{anyhow, bail, Result};
+use ethers_providers::{Http, Provider, ProviderError, RpcError};
+use prover::eth_types::{l2_types::BlockTrace, U64};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::mpsc, task::JoinHandle, time::Instant};
 
+/// How the `Client` requests l2geth.
+#[derive(Debug, Clone)]
+pub struct ClientPolicy {
+    /// Timeout of each request.
+    pub timeout: Duration,
+    /// Retries of a retriable error, the backoff doubling from `initial_backoff` each time.
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Max requests per second, shared by all the clones of a client.
+    pub max_requests_per_sec: Option<f64>,
+    /// JSON-RPC error codes worth a retry, like rate limiting (-32005) or internal errors
+    /// (-32603). Other error responses are fatal.
+    pub retriable_codes: Vec<i64>,
+}
+
+impl Default for ClientPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(120),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            max_requests_per_sec: None,
+            retriable_codes: vec![-32005, -32603],
+        }
+    }
+}
+
+impl ClientPolicy {
+    /// Returns whether a failed request may succeed when retried.
+    pub fn classify(&self, err: &anyhow::Error) -> ErrorKind {
+        if err.is::<tokio::time::error::Elapsed>() {
+            return ErrorKind::Retriable;
+        }
+        let Some(err) = err.downcast_ref::<ProviderError>() else {
+            return ErrorKind::Fatal;
+        };
+        if let Some(response) = err.as_error_response() {
+            return if self.retriable_codes.contains(&response.code) {
+                ErrorKind::Retriable
+            } else {
+                ErrorKind::Fatal
+            };
+        }
+        if err.as_serde_error().is_some() {
+            // l2geth answered something else than the expected JSON.
+            return ErrorKind::Fatal;
+        }
+        // Failed to reach l2geth.
+        ErrorKind::Retriable
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Retriable,
+    Fatal,
+}
+
+/// Spaces out requests to at most one per `interval`.
+#[derive(Debug)]
+struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_sec: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / requests_per_sec),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn acquire(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+/// How `Client::fetch_range` fetches traces. Each request follows the `ClientPolicy`.
+#[derive(Debug, Clone)]
+pub struct FetchConfig {
+    /// Number of blocks requested concurrently.
+    pub concurrency: usize,
+    /// Number of fetched blocks buffered ahead of the consumer.
+    pub prefetch: usize,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            prefetch: 16,
+        }
+    }
+}
+
+/// Traces of a block range fetched by `Client::fetch_range`, in block order.
+pub struct TraceStream {
+    rx: mpsc::Receiver<(i64, Result<BlockTrace>)>,
+    task: JoinHandle<()>,
+}
+
+impl TraceStream {
+    /// Returns the next block and its trace, or `None` after the last block.
+    pub async fn next(&mut self) -> Option<(i64, Result<BlockTrace>)> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for TraceStream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Per-block fetches of `Client::fetch_range`, in block order. They are aborted when dropped,
+/// which is also when the task of a dropped `TraceStream` is aborted.
+#[derive(Default)]
+struct InFlight(VecDeque<(i64, JoinHandle<Result<BlockTrace>>)>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        for (_, handle) in &self.0 {
+            handle.abort();
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Client {
     id: String,
     provider: Provider<Http>,
+    cache: Option<TraceCache>,
+    // Only serve traces from `cache`, never request the node.
+    offline: bool,
+    policy: ClientPolicy,
+    rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 impl Client {
@@ -15,58 +165,188 @@ impl Client {
         Ok(Self {
             id: id.to_string(),
             provider,
+            cache: None,
+            offline: false,
+            policy: ClientPolicy::default(),
+            rate_limiter: None,
         })
     }
 
+    pub fn with_policy(mut self, policy: ClientPolicy) -> Self {
+        self.rate_limiter = policy
+            .max_requests_per_sec
+            .map(|requests_per_sec| Arc::new(RateLimiter::new(requests_per_sec)));
+        self.policy = policy;
+        self
+    }
+
+    /// Puts `cache` in front of the node. Fetched block traces are written to it, and
+    /// following requests of the same block are served locally.
+    ///
+    /// When `offline` is set, the node is never requested, and a cache miss is an error.
+    pub fn with_cache(mut self, cache: TraceCache, offline: bool) -> Self {
+        self.cache = Some(cache);
+        self.offline = offline;
+        self
+    }
+
     pub async fn get_block_number(&self) -> Result<u64> {
-        Ok(self.provider.get_block_number().await?.as_u64())
+        if self.offline {
+            return self
+                .cache()?
+                .latest_block_number()?
+                .ok_or_else(|| anyhow!("{}: trace cache is empty", self.id));
+        }
+
+        let block_num: U64 = self
+            .request("eth_blockNumber", serde_json::json!([]))
+            .await?;
+        Ok(block_num.as_u64())
+    }
+
+    pub async fn get_chain_id(&self) -> Result<u64> {
+        if self.offline {
+            bail!("{}: chain id is not available in offline mode", self.id);
+        }
+
+        let chain_id: U64 = self.request("eth_chainId", serde_json::json!([])).await?;
+        Ok(chain_id.as_u64())
     }
+
     pub async fn get_txbytx_trace_by_num(&self, block_num: i64) -> Result<Vec<BlockTrace>> {
+        if self.offline {
+            bail!(
+                "{}: tx-by-tx trace of block {block_num} is not available in offline mode",
+                self.id
+            );
+        }
+
         let params =
             serde_json::json!([format!("{block_num:#x}"), {"StorageProofFormat": "legacy"}]);
-        let trace = self
-            .provider
-            .request("scroll_getTxByTxBlockTrace", params)
+        self.request("scroll_getTxByTxBlockTrace", params).await
+    }
+
+    pub async fn get_block_trace_by_num(&self, block_num: i64) -> Result<BlockTrace> {
+        self.get_block_trace(&TraceRequest::by_number(block_num as u64))
+            .await
+    }
+
+    /// Returns the trace of `request`. Only the native traces (see `TraceRequest::is_default`)
+    /// go through the trace cache.
+    pub async fn get_block_trace(&self, request: &TraceRequest) -> Result<BlockTrace> {
+        let block = request.block();
+        let cache = request
+            .block_num()
+            .filter(|_| request.is_default())
+            .and_then(|block_num| Some((self.cache.as_ref()?, block_num as i64)));
+        if let Some((cache, block_num)) = cache {
+            if let Some(trace) = cache.get(block_num)? {
+                log::info!("{}: trace of block {} is cached", self.id, block_num);
+                return Ok(trace);
+            }
+        }
+        if self.offline {
+            bail!(
+                "{}: trace of block {block} is not in trace cache (offline mode)",
+                self.id
+            );
+        }
+
+        log::info!("{}: requesting trace of block {}", self.id, block);
+        let trace: BlockTrace = self
+            .request("scroll_getBlockTraceByNumberOrHash", request.params())
             .await?;
+
+        if let Some((cache, block_num)) = cache {
+            cache.put(block_num, &trace)?;
+        }
         Ok(trace)
     }
 
-    // when override_curie == true,
-    //   we will force curie hard fork when tracing
-    pub async fn get_block_trace_by_num(
+    /// Fetches the traces of `begin_block..=end_block` in the background, with up to
+    /// `config.concurrency` requests in flight and `config.prefetch` traces buffered. Traces are
+    /// delivered in block order, and each block is delivered once, with the error of its last
+    /// attempt if it failed.
+    pub fn fetch_range(
         &self,
-        block_num: i64,
-        override_curie: bool,
-    ) -> Result<BlockTrace> {
-        log::info!("{}: requesting trace of block {}", self.id, block_num);
-
-        let params = if override_curie {
-            // curl -s -H 'Content-Type: application/json' -X POST --data
-            // '{"jsonrpc":"2.0","method":"scroll_getBlockTraceByNumberOrHash",
-            // "params": ["0x485490", {"overrides": {"curieBlock":1}}], "id": 99}'
-            // 127.0.0.1:8545
-            #[derive(Serialize)]
-            struct ChainConfig {
-                #[serde(rename = "curieBlock")]
-                curie_block: usize,
+        begin_block: i64,
+        end_block: i64,
+        config: &FetchConfig,
+    ) -> TraceStream {
+        let (tx, rx) = mpsc::channel(config.prefetch.max(1));
+        let client = self.clone();
+        let config = config.clone();
+        let task = tokio::spawn(async move {
+            let mut in_flight = InFlight::default();
+            let mut next_block = begin_block;
+            loop {
+                while in_flight.0.len() < config.concurrency.max(1) && next_block <= end_block {
+                    let client = client.clone();
+                    let block_num = next_block;
+                    let handle =
+                        tokio::spawn(async move { client.get_block_trace_by_num(block_num).await });
+                    in_flight.0.push_back((block_num, handle));
+                    next_block += 1;
+                }
+                // Only popped once done, so it's aborted with the others if the task is.
+                let Some((block_num, handle)) = in_flight.0.front_mut() else {
+                    break;
+                };
+                let block_num = *block_num;
+                let result = handle.await.unwrap_or_else(|e| {
+                    Err(anyhow!("fetching trace of block {block_num} panicked: {e}"))
+                });
+                in_flight.0.pop_front();
+                if tx.send((block_num, result)).await.is_err() {
+                    // The stream is dropped.
+                    break;
+                }
+            }
+        });
+
+        TraceStream { rx, task }
+    }
+
+    /// Sends a JSON-RPC request following the `ClientPolicy`.
+    async fn request<T>(&self, method: &str, params: Value) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned + std::fmt::Debug + Send,
+    {
+        let mut backoff = self.policy.initial_backoff;
+        let mut retries = 0;
+        loop {
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.acquire().await;
             }
-            #[derive(Serialize)]
-            struct TraceConfig {
-                overrides: ChainConfig,
+            let result = tokio::time::timeout(
+                self.policy.timeout,
+                self.provider.request::<_, T>(method, params.clone()),
+            )
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|result| result.map_err(anyhow::Error::from));
+
+            match result {
+                Err(e)
+                    if retries < self.policy.max_retries
+                        && self.policy.classify(&e) == ErrorKind::Retriable =>
+                {
+                    retries += 1;
+                    log::warn!(
+                        "{}: {method} failed, retry {retries} in {backoff:?}: {e}",
+                        self.id
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.policy.max_backoff);
+                }
+                result => return result,
             }
-            let override_param = TraceConfig {
-                overrides: ChainConfig {
-                    curie_block: 1, // any small value could be ok
-                },
-            };
-            serde_json::json!([format!("{block_num:#x}"), override_param])
-        } else {
-            serde_json::json!([format!("{block_num:#x}"), {"StorageProofFormat": "legacy"}])
-        };
-        let trace = self
-            .provider
-            .request("scroll_getBlockTraceByNumberOrHash", params)
-            .await?;
-        Ok(trace)
+        }
+    }
+
+    fn cache(&self) -> Result<&TraceCache> {
+        self.cache
+            .as_ref()
+            .ok_or_else(|| anyhow!("{}: no trace cache configured", self.id))
     }
 }
@@ -1,6 +1,21 @@
+pub mod batch_builder;
 pub mod capacity_checker;
+pub mod ccc_report;
+pub mod chunk_builder;
+pub mod chunk_info;
+pub mod constants;
+pub mod fake_l2geth;
+pub mod fake_rollupscan;
+pub mod fork_impact;
+mod http;
 pub mod l2geth;
 pub mod mock;
 pub mod prove;
+pub mod rollupscan;
+pub mod simulation;
 pub mod test_util;
+pub mod trace_cache;
+pub mod trace_file;
+pub mod trace_manifest;
+pub mod trace_request;
 mod verifier;
@@ -1,9 +1,10 @@
 use halo2_proofs::{halo2curves::bn256::Bn256, poly::kzg::commitment::ParamsKZG};
 use prover::{
     get_blob_bytes, BatchData, BatchProofV2, BatchProver, BatchProvingTask, BatchVerifier,
-    BundleProvingTask, ChunkInfo, ChunkProver, ChunkProvingTask, ChunkVerifier, MAX_AGG_SNARKS,
+    BundleProvingTask, ChunkInfo, ChunkProofV2, ChunkProver, ChunkProvingTask, ChunkVerifier,
+    MAX_AGG_SNARKS,
 };
-use std::{collections::BTreeMap, env, time::Instant};
+use std::{collections::BTreeMap, env, fmt, time::Instant};
 
 use crate::verifier::EVMVerifier;
 
@@ -20,6 +21,24 @@ pub fn new_batch_prover<'a>(
     prover
 }
 
+/// A failed step of `try_prove_and_verify_*`.
+#[derive(Debug, Clone)]
+pub enum ProveFailure {
+    /// The prover failed to generate the proof.
+    Prove(String),
+    /// The generated proof doesn't verify.
+    Verify(String),
+}
+
+impl fmt::Display for ProveFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Prove(msg) => write!(f, "proving err: {msg}"),
+            Self::Verify(msg) => write!(f, "verifying err: {msg}"),
+        }
+    }
+}
+
 pub fn prove_and_verify_chunk(
     chunk: ChunkProvingTask,
     chunk_identifier: Option<&str>,
@@ -32,11 +51,29 @@ pub fn prove_and_verify_chunk(
 
     let chunk_identifier =
         chunk_identifier.map_or_else(|| chunk.identifier(), |name| name.to_string());
+    try_prove_and_verify_chunk(
+        &mut prover,
+        chunk,
+        &chunk_identifier,
+        params_map,
+        output_dir,
+    )
+    .unwrap_or_else(|e| panic!("{e}"));
+}
 
+/// Like `prove_and_verify_chunk` with a constructed prover, but returns the failed step instead
+/// of panicking.
+pub fn try_prove_and_verify_chunk(
+    prover: &mut ChunkProver,
+    chunk: ChunkProvingTask,
+    chunk_identifier: &str,
+    params_map: &BTreeMap<u32, ParamsKZG<Bn256>>,
+    output_dir: &str,
+) -> Result<ChunkProofV2, ProveFailure> {
     let now = Instant::now();
     let chunk_proof = prover
-        .gen_halo2_chunk_proof(chunk, Some(&chunk_identifier), None, Some(output_dir))
-        .expect("cannot generate chunk snark");
+        .gen_halo2_chunk_proof(chunk, Some(chunk_identifier), None, Some(output_dir))
+        .map_err(|e| ProveFailure::Prove(format!("cannot generate chunk snark: {e}")))?;
     log::info!(
         "finish generating chunk snark, elapsed: {:?}",
         now.elapsed()
@@ -50,8 +87,10 @@ pub fn prove_and_verify_chunk(
     let verifier = ChunkVerifier::from_params_and_assets(params_map, output_dir);
     verifier
         .verify_chunk_proof(&chunk_proof)
-        .expect("should verify");
+        .map_err(|e| ProveFailure::Verify(format!("chunk proof doesn't verify: {e}")))?;
     log::info!("Verified chunk proof");
+
+    Ok(chunk_proof)
 }
 
 pub fn prove_and_verify_batch(
@@ -60,16 +99,26 @@ pub fn prove_and_verify_batch(
     batch_prover: &mut BatchProver,
     batch: BatchProvingTask,
 ) -> BatchProofV2 {
+    try_prove_and_verify_batch(params_map, output_dir, batch_prover, batch).unwrap_or_else(|e| {
+        log::error!("{e}");
+        panic!("{e}")
+    })
+}
+
+/// Like `prove_and_verify_batch`, but returns the failed step instead of panicking.
+pub fn try_prove_and_verify_batch(
+    params_map: &BTreeMap<u32, ParamsKZG<Bn256>>,
+    output_dir: &str,
+    batch_prover: &mut BatchProver,
+    batch: BatchProvingTask,
+) -> Result<BatchProofV2, ProveFailure> {
     let chunk_num = batch.chunk_proofs.len();
     log::info!("Prove batch BEGIN: chunk_num = {chunk_num}");
 
     let batch_id = batch.identifier();
-    let res_batch_proof = batch_prover.gen_batch_proof(batch, None, Some(output_dir));
-    if let Err(e) = res_batch_proof {
-        log::error!("proving err: {e}");
-        panic!("proving err: {:?}", e);
-    }
-    let batch_proof = res_batch_proof.unwrap();
+    let batch_proof = batch_prover
+        .gen_batch_proof(batch, None, Some(output_dir))
+        .map_err(|e| ProveFailure::Prove(format!("{e:?}")))?;
 
     env::set_var("BATCH_VK_FILENAME", format!("vk_batch_{batch_id}.vkey"));
     let verifier = BatchVerifier::from_params_and_assets(params_map, output_dir);
@@ -77,12 +126,12 @@ pub fn prove_and_verify_batch(
 
     verifier
         .verify_batch_proof(&batch_proof)
-        .expect("should verify");
+        .map_err(|e| ProveFailure::Verify(format!("batch proof doesn't verify: {e}")))?;
     log::info!("Verified batch proof");
 
     log::info!("Prove batch END: chunk_num = {chunk_num}");
 
-    batch_proof
+    Ok(batch_proof)
 }
 
 pub fn prove_and_verify_bundle(
@@ -90,20 +139,34 @@ pub fn prove_and_verify_bundle(
     prover: &mut BatchProver,
     bundle: BundleProvingTask,
 ) {
+    try_prove_and_verify_bundle(output_dir, prover, bundle).unwrap_or_else(|e| panic!("{e}"));
+}
+
+/// Like `prove_and_verify_bundle`, but returns the failed step instead of panicking.
+pub fn try_prove_and_verify_bundle(
+    output_dir: &str,
+    prover: &mut BatchProver,
+    bundle: BundleProvingTask,
+) -> Result<(), ProveFailure> {
     log::info!("Prove bundle BEGIN");
 
     let bundle_proof = prover
         .gen_bundle_proof(bundle, None, Some(output_dir))
-        .unwrap();
+        .map_err(|e| ProveFailure::Prove(format!("cannot generate bundle proof: {e}")))?;
 
     env::set_var("BATCH_VK_FILENAME", "vk_bundle_recursion.vkey");
     let verifier = EVMVerifier::from_dirs(output_dir);
     log::info!("Constructed bundle verifier");
 
-    assert!(verifier.verify_evm_proof(bundle_proof.calldata()));
+    if !verifier.verify_evm_proof(bundle_proof.calldata()) {
+        return Err(ProveFailure::Verify(
+            "bundle proof doesn't verify".to_string(),
+        ));
+    }
     log::info!("Verifier bundle proof");
 
     log::info!("Prove bundle END");
+    Ok(())
 }
 
 // `chunks` are unpadded
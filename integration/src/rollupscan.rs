@@ -0,0 +1,148 @@
+//! Client of the rollupscan (rollup explorer) API, which tells how blocks were packed into chunks
+//! and batches on chain.
+
+use anyhow::{anyhow, Result};
+use prover::eth_types::H256;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChunkInfo {
+    pub index: i64,
+    pub start_block_number: i64,
+    pub end_block_number: i64,
+}
+
+/// Metadata of a committed batch. Fields missing from older rollupscan deployments are optional.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BatchInfo {
+    pub index: i64,
+    pub hash: H256,
+    #[serde(default)]
+    pub parent_hash: Option<H256>,
+    /// Codec version of the batch.
+    #[serde(default)]
+    pub version: Option<u8>,
+    pub start_block_number: i64,
+    pub end_block_number: i64,
+    #[serde(default)]
+    pub start_chunk_index: Option<i64>,
+    #[serde(default)]
+    pub end_chunk_index: Option<i64>,
+    /// Number of L1 messages popped in this batch, including skipped ones.
+    #[serde(default)]
+    pub l1_messages_popped: Option<u64>,
+    /// Number of L1 messages popped before and in this batch.
+    #[serde(default)]
+    pub total_l1_messages_popped: Option<u64>,
+    #[serde(default)]
+    pub blob_versioned_hash: Option<H256>,
+    /// e.g. "committed", "finalized".
+    pub rollup_status: String,
+}
+
+impl BatchInfo {
+    /// Whether the batch is committed on L1, including finalized ones.
+    pub fn is_committed(&self) -> bool {
+        self.is_finalized() || self.rollup_status == "committed"
+    }
+
+    pub fn is_finalized(&self) -> bool {
+        self.rollup_status == "finalized"
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LastBatchIndexes {
+    pub all_index: i64,
+    pub committed_index: i64,
+    pub finalized_index: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct ChunksResponse {
+    pub batch_index: i64,
+    pub chunks: Option<Vec<ChunkInfo>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct BatchResponse {
+    pub batch: Option<BatchInfo>,
+}
+
+pub struct Client {
+    id: String,
+    api_url: String,
+}
+
+impl Client {
+    /// `api_url` is the base of the rollupscan API, e.g. `http://127.0.0.1:8560/api`.
+    /// For compatibility, the chunks API URL (`<api_url>/chunks`) is accepted too.
+    pub fn new(id: &str, api_url: &str) -> Self {
+        let api_url = api_url.trim_end_matches('/');
+        let api_url = api_url.strip_suffix("/chunks").unwrap_or(api_url);
+
+        Self {
+            id: id.to_string(),
+            api_url: api_url.to_string(),
+        }
+    }
+
+    pub async fn get_chunk_info_by_batch_index(
+        &self,
+        batch_index: i64,
+    ) -> Result<Option<Vec<ChunkInfo>>> {
+        log::info!(
+            "{}: requesting block traces of batch-{}",
+            self.id,
+            batch_index
+        );
+
+        let resp: ChunksResponse = self
+            .get("chunks", &[("batch_index", batch_index.to_string())])
+            .await?;
+        log::info!(
+            "{}: handling batch {}, chunk size {}",
+            self.id,
+            resp.batch_index,
+            resp.chunks.as_ref().map_or(0, Vec::len)
+        );
+
+        Ok(resp.chunks.filter(|chunks| !chunks.is_empty()))
+    }
+
+    pub async fn get_batch_info_by_index(&self, batch_index: i64) -> Result<Option<BatchInfo>> {
+        log::info!("{}: requesting info of batch-{}", self.id, batch_index);
+
+        let resp: BatchResponse = self
+            .get("batch", &[("index", batch_index.to_string())])
+            .await?;
+
+        Ok(resp.batch)
+    }
+
+    pub async fn get_last_batch_indexes(&self) -> Result<LastBatchIndexes> {
+        self.get("last_batch_indexes", &[]).await
+    }
+
+    /// Returns the latest committed batch.
+    pub async fn get_latest_batch_info(&self) -> Result<BatchInfo> {
+        let indexes = self.get_last_batch_indexes().await?;
+        self.get_batch_info_by_index(indexes.committed_index)
+            .await?
+            .ok_or_else(|| anyhow!("latest batch {} not found", indexes.committed_index))
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        api: &str,
+        params: &[(&str, String)],
+    ) -> Result<T> {
+        let url = Url::parse_with_params(&format!("{}/{api}", self.api_url), params)?;
+
+        let resp: String = reqwest::get(url).await?.error_for_status()?.text().await?;
+        log::debug!("{}: rollupscan response = {}", self.id, resp);
+
+        Ok(serde_json::from_str(&resp)?)
+    }
+}
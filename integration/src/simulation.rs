@@ -0,0 +1,129 @@
+//! Utilization statistics of simulated chunk and batch proposers, used to tune the proposer
+//! limits without proving anything.
+
+use crate::{
+    batch_builder::{Batch, BatchLimit},
+    chunk_builder::{Chunk, ChunkLimit},
+};
+use std::{collections::BTreeMap, fmt};
+
+/// Samples of a metric, summarized by their percentiles.
+#[derive(Debug, Clone, Default)]
+pub struct Distribution {
+    values: Vec<f64>,
+}
+
+impl Distribution {
+    pub fn push(&mut self, value: f64) {
+        self.values.push(value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.values.is_empty() {
+            return 0.0;
+        }
+        self.values.iter().sum::<f64>() / self.values.len() as f64
+    }
+
+    /// Returns the nearest-rank percentile, `p` in `[0, 100]`.
+    pub fn percentile(&self, p: f64) -> f64 {
+        if self.values.is_empty() {
+            return 0.0;
+        }
+        let mut sorted = self.values.clone();
+        sorted.sort_by(f64::total_cmp);
+        let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1]
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "n {} min {:.3} p10 {:.3} p50 {:.3} p90 {:.3} max {:.3} mean {:.3}",
+            self.len(),
+            self.percentile(0.0),
+            self.percentile(10.0),
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(100.0),
+            self.mean(),
+        )
+    }
+}
+
+/// Stats of the chunks and batches built by the proposers.
+#[derive(Debug, Clone, Default)]
+pub struct SimulationStats {
+    pub blocks_per_chunk: Distribution,
+    pub txs_per_chunk: Distribution,
+    /// Gas of a chunk per normalized row of its bottleneck sub-circuit.
+    pub gas_per_row: Distribution,
+    /// Number of chunks of which each sub-circuit is the bottleneck.
+    pub bottlenecks: BTreeMap<String, usize>,
+    pub chunks_closed_by: BTreeMap<ChunkLimit, usize>,
+    pub chunks_per_batch: Distribution,
+    pub blocks_per_batch: Distribution,
+    pub blob_utilization: Distribution,
+    pub batches_closed_by: BTreeMap<BatchLimit, usize>,
+}
+
+impl SimulationStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_chunk(&mut self, chunk: &Chunk) {
+        let stats = &chunk.stats;
+        self.blocks_per_chunk.push(stats.blocks as f64);
+        self.txs_per_chunk.push(stats.txs as f64);
+        if let Some(bottleneck) = stats
+            .row_usage
+            .row_usage_details
+            .iter()
+            .max_by_key(|r| r.row_number)
+        {
+            self.gas_per_row
+                .push(stats.gas as f64 / bottleneck.row_number.max(1) as f64);
+            *self.bottlenecks.entry(bottleneck.name.clone()).or_default() += 1;
+        }
+        *self.chunks_closed_by.entry(chunk.closed_by).or_default() += 1;
+    }
+
+    pub fn add_batch(&mut self, batch: &Batch) {
+        self.chunks_per_batch.push(batch.stats.chunks as f64);
+        self.blocks_per_batch.push(batch.stats.blocks as f64);
+        self.blob_utilization.push(batch.stats.blob_utilization());
+        *self.batches_closed_by.entry(batch.closed_by).or_default() += 1;
+    }
+
+    pub fn log_summary(&self) {
+        log::info!("simulation: {} chunks", self.blocks_per_chunk.len());
+        log::info!("simulation: blocks per chunk: {}", self.blocks_per_chunk);
+        log::info!("simulation: txs per chunk: {}", self.txs_per_chunk);
+        log::info!("simulation: gas per row: {}", self.gas_per_row);
+        for (name, count) in &self.bottlenecks {
+            log::info!("simulation: bottleneck {name}: {count} chunks");
+        }
+        for (limit, count) in &self.chunks_closed_by {
+            log::info!("simulation: chunks closed by {limit:?}: {count}");
+        }
+
+        log::info!("simulation: {} batches", self.chunks_per_batch.len());
+        log::info!("simulation: chunks per batch: {}", self.chunks_per_batch);
+        log::info!("simulation: blocks per batch: {}", self.blocks_per_batch);
+        log::info!("simulation: blob utilization: {}", self.blob_utilization);
+        for (limit, count) in &self.batches_closed_by {
+            log::info!("simulation: batches closed by {limit:?}: {count}");
+        }
+    }
+}
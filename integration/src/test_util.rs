@@ -1,14 +1,26 @@
 use std::{
     io::Read,
+    ops::RangeInclusive,
     path::{Path, PathBuf},
 };
 
-use glob::glob;
-use prover::{eth_types::l2_types::BlockTrace, get_block_trace_from_file, read_env_var};
+use anyhow::{anyhow, bail, Context, Result};
+use itertools::Itertools;
+use prover::{eth_types::l2_types::BlockTrace, read_env_var};
+
+use crate::{
+    fake_l2geth::FakeL2geth,
+    trace_file::{block_num_of_file, read_block_trace, Compression},
+};
 
 pub const ASSETS_DIR: &str = "./test_assets";
 pub const PARAMS_DIR: &str = "./params";
 
+/// A small chunk of contiguous mainnet blocks, used as the fixture of the unit tests.
+pub const FIXTURE_CHUNK_DIR: &str = "tests/extra_traces/batch_25/chunk_112";
+/// Blocks of `FIXTURE_CHUNK_DIR`.
+pub const FIXTURE_CHUNK_BLOCKS: RangeInclusive<u64> = 1341..=1350;
+
 pub fn read_all<P>(filename: P) -> Vec<u8>
 where
     P: AsRef<Path>,
@@ -28,34 +40,158 @@ pub fn trace_path_for_test() -> String {
 }
 
 pub fn load_chunk_for_test() -> (Vec<String>, Vec<BlockTrace>) {
-    load_chunk(&trace_path_for_test())
+    let trace_path = trace_path_for_test();
+    load_chunk(&trace_path).unwrap_or_else(|e| panic!("failed to load {trace_path}: {e:#}"))
+}
+
+/// Loads the traces of `FIXTURE_CHUNK_DIR`.
+pub fn load_fixture_chunk() -> Vec<BlockTrace> {
+    load_chunk(FIXTURE_CHUNK_DIR)
+        .unwrap_or_else(|e| panic!("failed to load {FIXTURE_CHUNK_DIR}: {e:#}"))
+        .1
+}
+
+/// Copies the trace files of `block_nums` of `FIXTURE_CHUNK_DIR` into `dst`, which is emptied
+/// first. Returns the paths of the copies.
+pub fn copy_fixture_chunk(
+    dst: impl AsRef<Path>,
+    block_nums: impl IntoIterator<Item = u64>,
+) -> Vec<PathBuf> {
+    let dst = dst.as_ref();
+    let _ = std::fs::remove_dir_all(dst);
+    std::fs::create_dir_all(dst)
+        .unwrap_or_else(|e| panic!("failed to create {}: {e}", dst.display()));
+    block_nums
+        .into_iter()
+        .map(|block_num| {
+            let file_name = format!("block_{block_num}.json");
+            let (src, dst) = (
+                Path::new(FIXTURE_CHUNK_DIR).join(&file_name),
+                dst.join(&file_name),
+            );
+            std::fs::copy(&src, &dst).unwrap_or_else(|e| {
+                panic!("failed to copy {} to {}: {e}", src.display(), dst.display())
+            });
+            dst
+        })
+        .collect()
+}
+
+/// Serves the traces under `trace_dir` with a `FakeL2geth` on a free local port, and returns
+/// its URL.
+pub async fn spawn_fake_l2geth(trace_dir: impl AsRef<Path>) -> String {
+    let trace_dir = trace_dir.as_ref();
+    let addr = FakeL2geth::from_trace_dir(trace_dir)
+        .unwrap_or_else(|e| panic!("failed to index {}: {e:#}", trace_dir.display()))
+        .spawn("127.0.0.1:0")
+        .await
+        .unwrap_or_else(|e| panic!("failed to spawn fake l2geth: {e:#}"));
+    format!("http://{addr}")
+}
+
+/// Loads the traces of a chunk, from a trace file or a dir of `block_<n>.json` files (or
+/// compressed, see `trace_file`). Each trace must be of the block its file is named after, and
+/// the blocks of a dir must be contiguous and link to each other by parent hash.
+pub fn load_chunk(trace_path: &str) -> Result<(Vec<String>, Vec<BlockTrace>)> {
+    let (paths, traces) = load_chunk_unchecked(trace_path)?;
+    check_linkage(&paths, &traces)?;
+    Ok((paths, traces))
 }
 
-pub fn load_chunk(trace_path: &str) -> (Vec<String>, Vec<BlockTrace>) {
-    let paths: Vec<String> = if !std::fs::metadata(trace_path).unwrap().is_dir() {
+/// Like `load_chunk`, but the blocks of a dir may have gaps, for the test dirs which are samples
+/// of a chunk.
+pub fn load_chunk_unchecked(trace_path: &str) -> Result<(Vec<String>, Vec<BlockTrace>)> {
+    let metadata = std::fs::metadata(trace_path)
+        .with_context(|| format!("failed to read trace path {trace_path}"))?;
+    let paths: Vec<String> = if !metadata.is_dir() {
         vec![trace_path.to_string()]
     } else {
         // Nested dirs are not allowed
-        let mut file_names: Vec<String> = glob(&format!("{trace_path}/*.json"))
-            .unwrap()
-            .map(|p| p.unwrap().to_str().unwrap().to_string())
-            .collect();
-        file_names.sort_by_key(|s| {
-            let path = Path::new(s);
-            let basename = path.file_stem().unwrap().to_str().unwrap();
-            basename
-                .trim_start_matches("block_")
-                .parse::<u32>()
-                .unwrap()
-        });
-        file_names
+        let mut numbered_paths = vec![];
+        for path in read_dir(trace_path)? {
+            if Compression::of_path(&path).is_none() {
+                continue;
+            }
+            let block_num = block_num_of_file(&path).ok_or_else(|| {
+                anyhow!(
+                    "unexpected trace file {}, expected block_<n>.json(.gz|.zst)",
+                    path.display()
+                )
+            })?;
+            numbered_paths.push((block_num, path.to_string_lossy().into_owned()));
+        }
+        if numbered_paths.is_empty() {
+            bail!("no block traces in {trace_path}");
+        }
+        numbered_paths.sort();
+        if let Some(((block_num, a), (_, b))) = numbered_paths
+            .iter()
+            .tuple_windows()
+            .find(|((a, _), (b, _))| a == b)
+        {
+            bail!("block {block_num} has two trace files, {a} and {b}");
+        }
+        numbered_paths.into_iter().map(|(_, path)| path).collect()
     };
     log::info!("test cases traces: {:?}", paths);
-    let traces: Vec<_> = paths.iter().map(get_block_trace_from_file).collect();
-    (paths, traces)
+    let traces = paths
+        .iter()
+        .map(read_block_trace)
+        .collect::<Result<Vec<_>>>()?;
+    check_block_nums(&paths, &traces)?;
+    Ok((paths, traces))
 }
 
-pub fn load_batch(batch_dir: &str) -> anyhow::Result<Vec<String>> {
+/// Checks that each trace is of the block its file is named after.
+fn check_block_nums(paths: &[String], traces: &[BlockTrace]) -> Result<()> {
+    for (path, trace) in paths.iter().zip(traces) {
+        let block_num = trace.header.number.unwrap_or_default().as_u64();
+        if let Some(expected) = block_num_of_file(Path::new(path)) {
+            if block_num != expected {
+                bail!("{path} is the trace of block {block_num}, expected {expected}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the blocks are contiguous and link to each other by parent hash.
+fn check_linkage(paths: &[String], traces: &[BlockTrace]) -> Result<()> {
+    let block_num = |trace: &BlockTrace| trace.header.number.unwrap_or_default().as_u64();
+    for ((_, prev), (path, trace)) in paths.iter().zip(traces).tuple_windows() {
+        let (prev_num, num) = (block_num(prev), block_num(trace));
+        if num != prev_num + 1 {
+            bail!("block {num} ({path}) is not contiguous with block {prev_num}");
+        }
+        if Some(trace.header.parent_hash) != prev.header.hash {
+            bail!("parent hash of block {num} ({path}) is not the hash of block {prev_num}");
+        }
+    }
+    Ok(())
+}
+
+/// Returns the chunk dirs of a batch dir, in order. Each chunk is loaded with `load_chunk`, and
+/// the first block of a chunk must follow the last block of the previous one.
+pub fn load_batch(batch_dir: &str) -> Result<Vec<String>> {
+    let sorted_dirs = load_batch_unchecked(batch_dir)?;
+    let mut prev: Option<(String, BlockTrace)> = None;
+    for chunk_dir in &sorted_dirs {
+        let (mut paths, mut traces) = load_chunk(chunk_dir)?;
+        if let Some((prev_path, prev_trace)) = prev.take() {
+            check_linkage(
+                &[prev_path, paths[0].clone()],
+                &[prev_trace, traces[0].clone()],
+            )
+            .with_context(|| format!("{chunk_dir} doesn't follow the previous chunk"))?;
+        }
+        prev = paths.pop().zip(traces.pop());
+    }
+    Ok(sorted_dirs)
+}
+
+/// Like `load_batch`, but only lists the chunk dirs, for the test batches of sampled chunks. Load
+/// them with `load_chunk_unchecked`.
+pub fn load_batch_unchecked(batch_dir: &str) -> Result<Vec<String>> {
     let sorted_dirs = read_dir_recursive(batch_dir, "chunk_")?;
     log::info!("batch content: {:?}", sorted_dirs);
     Ok(sorted_dirs)
@@ -63,23 +199,28 @@ pub fn load_batch(batch_dir: &str) -> anyhow::Result<Vec<String>> {
 
 /// Reads inside a directory recursively and returns paths to all sub-directories that match the
 /// given prefix.
-pub fn read_dir_recursive(dir: impl AsRef<Path>, prefix: &str) -> anyhow::Result<Vec<String>> {
-    let mut sorted_dirs: Vec<String> = std::fs::read_dir(dir)?
-        .filter_map(|entry| entry.ok())
-        .map(|entry| entry.path())
-        .filter(|path| path.is_dir())
-        .map(|path| path.to_string_lossy().into_owned())
-        .collect::<Vec<String>>();
-    sorted_dirs.sort_by_key(|s| {
-        let path = Path::new(s);
-        let dir_name = path.file_name().unwrap().to_string_lossy();
-        dir_name.trim_start_matches(prefix).parse::<u32>().unwrap()
-    });
-    Ok(sorted_dirs)
+pub fn read_dir_recursive(dir: impl AsRef<Path>, prefix: &str) -> Result<Vec<String>> {
+    let dir = dir.as_ref();
+    let mut numbered_dirs = vec![];
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("failed to read dir {}", dir.display()))?
+    {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let num = path
+            .file_name()
+            .and_then(|name| name.to_str()?.strip_prefix(prefix)?.parse::<u32>().ok())
+            .ok_or_else(|| anyhow!("unexpected dir {}, expected {prefix}<n>", path.display()))?;
+        numbered_dirs.push((num, path.to_string_lossy().into_owned()));
+    }
+    numbered_dirs.sort();
+    Ok(numbered_dirs.into_iter().map(|(_, path)| path).collect())
 }
 
 /// Reads inside a directory and returns all files.
-pub fn read_dir(dir: impl AsRef<Path>) -> anyhow::Result<Vec<PathBuf>> {
+pub fn read_dir(dir: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
     let mut sorted_files = std::fs::read_dir(dir)?
         .filter_map(|entry| entry.ok())
         .map(|entry| entry.path())
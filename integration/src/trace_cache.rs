@@ -0,0 +1,239 @@
+use crate::{
+    trace_file::{
+        block_num_of_file, decompress, parse_block_trace, trace_files, trace_path,
+        write_trace_file, Compression,
+    },
+    trace_manifest::{sha256_hex, ManifestEntry},
+};
+use anyhow::{bail, Context, Result};
+use prover::eth_types::l2_types::BlockTrace;
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+/// File name of the index of a trace cache, in its dir.
+pub const INDEX_FILE: &str = "index.jsonl";
+
+/// On-disk cache of block traces.
+///
+/// Each trace is stored as `<dir>/block_<n>.json` (or compressed, see `with_compression`), the
+/// same layout `test_util::load_chunk` reads, so a cache dir can be used directly as a chunk
+/// trace dir. Cached traces are read whatever their compression.
+///
+/// The chain id, hash and checksum of each trace are recorded in `<dir>/index.jsonl` when it's
+/// put, and checked when it's read, so a trace of another chain (see `with_chain_id`), a changed
+/// file, or a block which doesn't link to its cached neighbors (e.g. reorged) is an error rather
+/// than served silently.
+#[derive(Debug, Clone)]
+pub struct TraceCache {
+    dir: PathBuf,
+    compression: Compression,
+    chain_id: Option<u64>,
+    index: Arc<Mutex<BTreeMap<u64, ManifestEntry>>>,
+}
+
+impl TraceCache {
+    /// Opens the cache in `dir`. The traces of a dir without an index (e.g. a chunk trace dir)
+    /// are indexed first, and the index is only written with the first trace put.
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create trace cache dir {}", dir.display()))?;
+
+        let index_path = dir.join(INDEX_FILE);
+        let index = if index_path.exists() {
+            load_index(&index_path)?
+        } else {
+            let entries = trace_files(&dir)?
+                .iter()
+                .map(|path| ManifestEntry::from_file(&dir, path))
+                .collect::<Result<Vec<_>>>()?;
+            if !entries.is_empty() {
+                log::info!(
+                    "trace cache: indexed {} traces in {}",
+                    entries.len(),
+                    dir.display()
+                );
+            }
+            entries
+                .into_iter()
+                .map(|entry| (entry.block_num, entry))
+                .collect()
+        };
+
+        Ok(Self {
+            dir,
+            compression: Compression::None,
+            chain_id: None,
+            index: Arc::new(Mutex::new(index)),
+        })
+    }
+
+    /// Compression of the traces put in the cache.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Chain of the cached traces. Traces of any other chain are neither put nor read.
+    pub fn with_chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = Some(chain_id);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path of the trace of `block_num` put in the cache.
+    pub fn path_of(&self, block_num: i64) -> PathBuf {
+        trace_path(&self.dir, block_num as u64, self.compression)
+    }
+
+    /// Returns the cached trace of `block_num`, or `None` on a cache miss.
+    pub fn get(&self, block_num: i64) -> Result<Option<BlockTrace>> {
+        let (entry, prev, next) = {
+            let index = self.index();
+            let Some(entry) = index.get(&(block_num as u64)).cloned() else {
+                let unindexed = Compression::ALL
+                    .into_iter()
+                    .map(|compression| trace_path(&self.dir, block_num as u64, compression))
+                    .find(|path| path.exists());
+                if let Some(path) = unindexed {
+                    bail!(
+                        "cached trace {} is not in {INDEX_FILE}, remove it or the index to re-index the cache",
+                        path.display()
+                    );
+                }
+                return Ok(None);
+            };
+            let neighbor = |num: Option<u64>| index.get(&num?).cloned();
+            let (prev, next) = (
+                entry.block_num.checked_sub(1),
+                entry.block_num.checked_add(1),
+            );
+            (entry, neighbor(prev), neighbor(next))
+        };
+
+        let path = self.dir.join(&entry.path);
+        let bytes = fs::read(&path)
+            .with_context(|| format!("failed to read cached trace {}", path.display()))?;
+        if sha256_hex(&bytes) != entry.sha256 {
+            bail!(
+                "cached trace {} changed since it was put in the cache",
+                path.display()
+            );
+        }
+        self.check_chain_id(block_num, entry.chain_id)?;
+        if prev.map_or(false, |prev| prev.block_hash != entry.parent_hash)
+            || next.map_or(false, |next| next.parent_hash != entry.block_hash)
+        {
+            bail!(
+                "cached trace of block {block_num} doesn't link to the cached blocks next to it, the chain may have reorged"
+            );
+        }
+
+        let trace = decompress(&bytes)
+            .and_then(|json| parse_block_trace(&json))
+            .with_context(|| format!("failed to parse cached trace {}", path.display()))?;
+        let cached_num = trace.header.number.map(|n| n.as_u64() as i64);
+        if cached_num != Some(block_num) || trace.header.hash != Some(entry.block_hash) {
+            bail!(
+                "cached trace {} is of block {cached_num:?} with hash {:?}, expected block {block_num} with hash {:?}",
+                path.display(),
+                trace.header.hash,
+                entry.block_hash
+            );
+        }
+
+        Ok(Some(trace))
+    }
+
+    pub fn put(&self, block_num: i64, trace: &BlockTrace) -> Result<()> {
+        self.check_chain_id(block_num, trace.chain_id)?;
+        let path = self.path_of(block_num);
+        write_trace_file(&path, &serde_json::to_vec(trace)?)?;
+
+        let bytes = fs::read(&path)
+            .with_context(|| format!("failed to read cached trace {}", path.display()))?;
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let entry = ManifestEntry::of_trace(file_name.into_owned(), trace, &bytes);
+        let index_path = self.dir.join(INDEX_FILE);
+        let mut index = self.index();
+        if !index_path.exists() {
+            for indexed in index.values() {
+                append_index(&index_path, indexed)?;
+            }
+        }
+        append_index(&index_path, &entry)?;
+        index.insert(entry.block_num, entry);
+        Ok(())
+    }
+
+    /// Returns the lowest and the highest block numbers in the cache.
+    pub fn block_range(&self) -> Result<Option<(u64, u64)>> {
+        let index = self.index();
+        Ok(index
+            .keys()
+            .next()
+            .zip(index.keys().next_back())
+            .map(|(a, b)| (*a, *b)))
+    }
+
+    /// Returns the highest block number in the cache.
+    pub fn latest_block_number(&self) -> Result<Option<u64>> {
+        Ok(self.index().keys().next_back().copied())
+    }
+
+    fn index(&self) -> MutexGuard<'_, BTreeMap<u64, ManifestEntry>> {
+        self.index.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn check_chain_id(&self, block_num: i64, chain_id: u64) -> Result<()> {
+        match self.chain_id {
+            Some(expected) if expected != chain_id => bail!(
+                "trace of block {block_num} is of chain {chain_id}, the trace cache is of chain {expected}"
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Loads an index, where a later entry of a block replaces the earlier ones.
+fn load_index(path: &Path) -> Result<BTreeMap<u64, ManifestEntry>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("failed to read trace cache index {}", path.display()))?;
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let entry: ManifestEntry = serde_json::from_str(line)
+                .with_context(|| format!("failed to parse trace cache index {}", path.display()))?;
+            if block_num_of_file(Path::new(&entry.path)) != Some(entry.block_num) {
+                bail!(
+                    "{} indexes {} as block {}",
+                    path.display(),
+                    entry.path,
+                    entry.block_num
+                );
+            }
+            Ok((entry.block_num, entry))
+        })
+        .collect()
+}
+
+fn append_index(path: &Path, entry: &ManifestEntry) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open trace cache index {}", path.display()))?;
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    file.write_all(&line)
+        .with_context(|| format!("failed to write trace cache index {}", path.display()))
+}
@@ -0,0 +1,205 @@
+//! Block trace files, either plain `block_<n>.json` or compressed `block_<n>.json.gz` and
+//! `block_<n>.json.zst`.
+
+use anyhow::{bail, Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder};
+use prover::eth_types::l2_types::BlockTrace;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    borrow::Cow,
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub const ALL: [Compression; 3] = [Compression::None, Compression::Gzip, Compression::Zstd];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::None => ".json",
+            Compression::Gzip => ".json.gz",
+            Compression::Zstd => ".json.zst",
+        }
+    }
+
+    /// Compression of a trace file by its extension, `None` if it isn't a trace file.
+    pub fn of_path(path: &Path) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?;
+        // `.json` is a suffix of neither of the others.
+        Self::ALL
+            .into_iter()
+            .find(|c| file_name.ends_with(c.extension()))
+    }
+
+    /// Compression of a file content by its magic bytes.
+    pub fn of_bytes(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if bytes.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    pub fn compress<'a>(self, bytes: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        Ok(match self {
+            Compression::None => Cow::Borrowed(bytes),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                Cow::Owned(encoder.finish()?)
+            }
+            Compression::Zstd => Cow::Owned(zstd::encode_all(bytes, 0)?),
+        })
+    }
+
+    pub fn decompress<'a>(self, bytes: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        Ok(match self {
+            Compression::None => Cow::Borrowed(bytes),
+            Compression::Gzip => {
+                let mut decompressed = vec![];
+                GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+                Cow::Owned(decompressed)
+            }
+            Compression::Zstd => Cow::Owned(zstd::decode_all(bytes)?),
+        })
+    }
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "none" => Compression::None,
+            "gzip" | "gz" => Compression::Gzip,
+            "zstd" | "zst" => Compression::Zstd,
+            _ => bail!("unknown compression {s}, expected none, gzip or zstd"),
+        })
+    }
+}
+
+/// Decompresses a trace file content, detected by its magic bytes.
+pub fn decompress(bytes: &[u8]) -> Result<Cow<'_, [u8]>> {
+    Compression::of_bytes(bytes).decompress(bytes)
+}
+
+/// Block number of a trace file named `block_<n>.json` (or `<n>.json`), or compressed.
+pub fn block_num_of_file(path: &Path) -> Option<u64> {
+    let file_name = path.file_name()?.to_str()?;
+    file_name
+        .strip_suffix(Compression::of_path(path)?.extension())?
+        .trim_start_matches("block_")
+        .parse()
+        .ok()
+}
+
+/// Path of the trace file of `block_num` in `dir`.
+pub fn trace_path(dir: &Path, block_num: u64, compression: Compression) -> PathBuf {
+    dir.join(format!("block_{block_num}{}", compression.extension()))
+}
+
+/// All trace files under `dir`, recursively, sorted by path.
+pub fn trace_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(trace_files(&path)?);
+        } else if block_num_of_file(&path).is_some() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Reads the JSON of a trace file, whatever its compression.
+pub fn read_trace_file(path: impl AsRef<Path>) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    let bytes =
+        fs::read(path).with_context(|| format!("failed to read trace {}", path.display()))?;
+    match Compression::of_bytes(&bytes) {
+        Compression::None => Ok(bytes),
+        compression => Ok(compression
+            .decompress(&bytes)
+            .with_context(|| format!("failed to decompress trace {}", path.display()))?
+            .into_owned()),
+    }
+}
+
+/// Writes the JSON of a trace file, compressed by the extension of `path`. It's written to a temp
+/// file first, so an interrupted write never leaves a truncated trace.
+pub fn write_trace_file(path: impl AsRef<Path>, json: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    let compression = Compression::of_path(path)
+        .with_context(|| format!("{} is not a trace file name", path.display()))?;
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, compression.compress(json)?)
+        .with_context(|| format!("failed to write trace {}", path.display()))?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("failed to write trace {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Converts a trace file to `compression`, in place. The new file is read back and checked to be
+/// the same JSON before the old one is removed. Returns the path of the new file.
+pub fn convert_trace_file(path: &Path, compression: Compression) -> Result<PathBuf> {
+    let file_name = path.file_name().and_then(|name| name.to_str());
+    let (stem, current) = file_name
+        .zip(Compression::of_path(path))
+        .and_then(|(name, current)| Some((name.strip_suffix(current.extension())?, current)))
+        .with_context(|| format!("{} is not a trace file name", path.display()))?;
+    if current == compression {
+        return Ok(path.to_path_buf());
+    }
+
+    let json = read_trace_file(path)?;
+    let new_path = path.with_file_name(format!("{stem}{}", compression.extension()));
+    write_trace_file(&new_path, &json)?;
+    if read_trace_file(&new_path)? != json {
+        fs::remove_file(&new_path)?;
+        bail!(
+            "round trip of {} through {compression:?} changed its content",
+            path.display()
+        );
+    }
+    fs::remove_file(path).with_context(|| format!("failed to remove {}", path.display()))?;
+
+    Ok(new_path)
+}
+
+/// Reads a trace file, of either a `BlockTrace` or a JSON-RPC response of it (as dumped by
+/// `download_trace.sh`).
+pub fn read_block_trace(path: impl AsRef<Path>) -> Result<BlockTrace> {
+    let path = path.as_ref();
+    parse_block_trace(&read_trace_file(path)?)
+        .with_context(|| format!("failed to parse trace {}", path.display()))
+}
+
+/// Parses the JSON of a trace file, see `read_block_trace`.
+pub fn parse_block_trace(json: &[u8]) -> Result<BlockTrace> {
+    let mut value: Value = serde_json::from_slice(json)?;
+    if let Some(result) = value.get_mut("result") {
+        value = result.take();
+    }
+    Ok(serde_json::from_value(value)?)
+}
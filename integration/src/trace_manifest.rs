@@ -0,0 +1,224 @@
+//! Manifest of a trace corpus (e.g. `tests/extra_traces`), recording what each trace file is and
+//! its checksum, so a corrupted or stray trace is found before a test trips over it.
+
+use crate::{
+    trace_file::{block_num_of_file, decompress, parse_block_trace, trace_files},
+    trace_request::Fork,
+};
+use anyhow::{bail, Context, Result};
+use itertools::Itertools;
+use prover::eth_types::{l2_types::BlockTrace, H256};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+/// File name of the manifest in the corpus root.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path of the trace file, relative to the corpus root.
+    pub path: String,
+    pub block_num: u64,
+    pub block_hash: H256,
+    pub parent_hash: H256,
+    pub chain_id: u64,
+    /// `None` before Bernoulli or on an unknown chain, see `Fork::of_block`.
+    pub fork: Option<Fork>,
+    pub tx_num: usize,
+    /// Hex SHA-256 of the trace file, as stored (compressed or not).
+    pub sha256: String,
+}
+
+impl ManifestEntry {
+    /// Reads and checks a trace file of the corpus.
+    pub fn from_file(root: &Path, path: &Path) -> Result<Self> {
+        let bytes =
+            fs::read(path).with_context(|| format!("failed to read trace {}", path.display()))?;
+        let trace = decompress(&bytes)
+            .and_then(|json| parse_block_trace(&json))
+            .with_context(|| format!("failed to parse trace {}", path.display()))?;
+        let block_num = trace
+            .header
+            .number
+            .with_context(|| format!("trace {} has no block number", path.display()))?
+            .as_u64();
+        if block_num_of_file(path) != Some(block_num) {
+            bail!(
+                "{} is the trace of block {block_num}, not of its file name",
+                path.display()
+            );
+        }
+
+        Ok(Self::of_trace(relative_path(root, path)?, &trace, &bytes))
+    }
+
+    /// Entry of a trace file at `path`, whose stored content is `bytes`.
+    pub fn of_trace(path: String, trace: &BlockTrace, bytes: &[u8]) -> Self {
+        let block_num = trace.header.number.unwrap_or_default().as_u64();
+        Self {
+            path,
+            block_num,
+            block_hash: trace.header.hash.unwrap_or_default(),
+            parent_hash: trace.header.parent_hash,
+            chain_id: trace.chain_id,
+            fork: Fork::of_block(trace.chain_id, block_num, trace.header.timestamp.as_u64()),
+            tx_num: trace.transactions.len(),
+            sha256: sha256_hex(bytes),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceManifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl TraceManifest {
+    /// Builds the manifest of all trace files under `root`, recursively.
+    pub fn generate(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref();
+        let entries = trace_files(root)?
+            .iter()
+            .map(|path| ManifestEntry::from_file(root, path))
+            .collect::<Result<Vec<_>>>()?;
+        log::info!(
+            "trace manifest: {} traces under {}",
+            entries.len(),
+            root.display()
+        );
+
+        Ok(Self { entries })
+    }
+
+    /// Regenerates the entries of the trace files under `dir`, a dir of the corpus at `root`,
+    /// keeping the entries of the other dirs.
+    pub fn update(&mut self, root: impl AsRef<Path>, dir: impl AsRef<Path>) -> Result<()> {
+        let (root, dir) = (root.as_ref(), dir.as_ref());
+        let dir_path = relative_path(root, dir)?;
+        let updated = trace_files(dir)?
+            .iter()
+            .map(|path| ManifestEntry::from_file(root, path))
+            .collect::<Result<Vec<_>>>()?;
+        log::info!(
+            "trace manifest: {} traces under {}",
+            updated.len(),
+            dir.display()
+        );
+
+        self.entries
+            .retain(|entry| !dir_path.is_empty() && !Path::new(&entry.path).starts_with(&dir_path));
+        self.entries.extend(updated);
+        self.entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = fs::read(path)
+            .with_context(|| format!("failed to read manifest {}", path.display()))?;
+        serde_json::from_slice(&bytes)
+            .with_context(|| format!("failed to parse manifest {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("failed to write manifest {}", path.display()))
+    }
+
+    /// Checks the trace files under `root` against the manifest: every listed file exists with
+    /// the recorded checksum, and no trace file is missing from the manifest.
+    pub fn validate(&self, root: impl AsRef<Path>) -> Result<()> {
+        let root = root.as_ref();
+        let mut problems = vec![];
+        let mut listed = BTreeSet::new();
+        for entry in &self.entries {
+            if !listed.insert(entry.path.as_str()) {
+                problems.push(format!("{} is listed more than once", entry.path));
+                continue;
+            }
+            match fs::read(root.join(&entry.path)) {
+                Ok(bytes) => {
+                    let sha256 = sha256_hex(&bytes);
+                    if sha256 != entry.sha256 {
+                        problems.push(format!(
+                            "{} has sha256 {sha256}, expected {}",
+                            entry.path, entry.sha256
+                        ));
+                    }
+                }
+                Err(e) => problems.push(format!("failed to read {}: {e}", entry.path)),
+            }
+        }
+        for path in trace_files(root)? {
+            let path = relative_path(root, &path)?;
+            if !listed.contains(path.as_str()) {
+                problems.push(format!("{path} is not in the manifest"));
+            }
+        }
+
+        if !problems.is_empty() {
+            bail!(
+                "{} problems in the trace manifest of {}:\n{}",
+                problems.len(),
+                root.display(),
+                problems.join("\n")
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks that the traces of each dir are of contiguous blocks, linked by parent hash. Not
+    /// part of `validate`, as some dirs of a corpus may be samples of a chunk, with gaps.
+    pub fn check_linkage(&self) -> Result<()> {
+        let mut dirs: BTreeMap<&str, Vec<&ManifestEntry>> = BTreeMap::new();
+        for entry in &self.entries {
+            let dir = entry.path.rsplit_once('/').map_or("", |(dir, _)| dir);
+            dirs.entry(dir).or_default().push(entry);
+        }
+
+        let mut problems = vec![];
+        for entries in dirs.values_mut() {
+            entries.sort_by_key(|entry| entry.block_num);
+            for (prev, entry) in entries.iter().tuple_windows() {
+                if entry.block_num != prev.block_num + 1 {
+                    problems.push(format!(
+                        "{} is not contiguous with block {}",
+                        entry.path, prev.block_num
+                    ));
+                } else if entry.parent_hash != prev.block_hash {
+                    problems.push(format!(
+                        "parent hash of {} is not the hash of block {}",
+                        entry.path, prev.block_num
+                    ));
+                }
+            }
+        }
+
+        if !problems.is_empty() {
+            bail!(
+                "{} problems in the linkage of the traces:\n{}",
+                problems.len(),
+                problems.join("\n")
+            );
+        }
+        Ok(())
+    }
+}
+
+fn relative_path(root: &Path, path: &Path) -> Result<String> {
+    Ok(path
+        .strip_prefix(root)
+        .with_context(|| format!("{} is not under {}", path.display(), root.display()))?
+        .to_string_lossy()
+        .into_owned())
+}
+
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
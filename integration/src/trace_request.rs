@@ -0,0 +1,184 @@
+//! Params of the `scroll_getBlockTraceByNumberOrHash` API of l2geth.
+
+use anyhow::{bail, Result};
+use prover::eth_types::H256;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockId {
+    Number(u64),
+    Hash(H256),
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockId::Number(num) => write!(f, "{num}"),
+            BlockId::Hash(hash) => write!(f, "{hash:?}"),
+        }
+    }
+}
+
+/// Format of the storage proofs in traces. `download_trace.sh` uses `Flatten`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageProofFormat {
+    #[default]
+    Legacy,
+    Flatten,
+}
+
+/// Hard forks which can be enabled from genesis by a chain config override.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fork {
+    Bernoulli,
+    Curie,
+    Darwin,
+    DarwinV2,
+}
+
+/// Fork activations of the known chains: chain id, Bernoulli and Curie blocks, Darwin and
+/// DarwinV2 times.
+const FORK_ACTIVATIONS: [(u64, u64, u64, u64, u64); 2] = [
+    // mainnet
+    (534352, 5_220_340, 7_096_836, 1_724_227_200, 1_725_264_000),
+    // sepolia
+    (534351, 3_747_132, 4_740_239, 1_723_622_400, 1_724_832_000),
+];
+
+impl Fork {
+    /// All forks, in activation order.
+    pub const ALL: [Fork; 4] = [Fork::Bernoulli, Fork::Curie, Fork::Darwin, Fork::DarwinV2];
+
+    /// The latest fork active at a block, `None` before Bernoulli or on an unknown chain.
+    pub fn of_block(chain_id: u64, block_num: u64, timestamp: u64) -> Option<Fork> {
+        let &(_, bernoulli, curie, darwin, darwin_v2) =
+            FORK_ACTIVATIONS.iter().find(|(id, ..)| *id == chain_id)?;
+        if timestamp >= darwin_v2 {
+            Some(Fork::DarwinV2)
+        } else if timestamp >= darwin {
+            Some(Fork::Darwin)
+        } else if block_num >= curie {
+            Some(Fork::Curie)
+        } else if block_num >= bernoulli {
+            Some(Fork::Bernoulli)
+        } else {
+            None
+        }
+    }
+
+    /// The field of the l2geth chain config activating the fork.
+    pub fn config_key(self) -> &'static str {
+        match self {
+            Fork::Bernoulli => "bernoulliBlock",
+            Fork::Curie => "curieBlock",
+            Fork::Darwin => "darwinTime",
+            Fork::DarwinV2 => "darwinv2Time",
+        }
+    }
+}
+
+impl FromStr for Fork {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "bernoulli" => Fork::Bernoulli,
+            "curie" => Fork::Curie,
+            "darwin" => Fork::Darwin,
+            "darwin-v2" | "darwinv2" => Fork::DarwinV2,
+            _ => bail!("unknown fork {s}, expected bernoulli, curie, darwin or darwin-v2"),
+        })
+    }
+}
+
+/// A request of a block trace.
+///
+/// ```ignore
+/// let request = TraceRequest::by_number(block_num)
+///     .storage_proof_format(StorageProofFormat::Flatten)
+///     .enable_fork(Fork::Darwin);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRequest {
+    block: BlockId,
+    storage_proof_format: StorageProofFormat,
+    // Chain config overrides, by the field names of the l2geth chain config.
+    overrides: BTreeMap<String, Value>,
+}
+
+impl TraceRequest {
+    pub fn by_number(block_num: u64) -> Self {
+        Self::new(BlockId::Number(block_num))
+    }
+
+    pub fn by_hash(block_hash: H256) -> Self {
+        Self::new(BlockId::Hash(block_hash))
+    }
+
+    fn new(block: BlockId) -> Self {
+        Self {
+            block,
+            storage_proof_format: StorageProofFormat::default(),
+            overrides: BTreeMap::new(),
+        }
+    }
+
+    pub fn storage_proof_format(mut self, format: StorageProofFormat) -> Self {
+        self.storage_proof_format = format;
+        self
+    }
+
+    /// Overrides a field of the chain config when tracing, e.g. `("curieBlock", 1)`.
+    pub fn override_config(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.overrides.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// Traces the block as if `fork`, and so all the forks before it, was enabled from genesis.
+    pub fn enable_fork(self, fork: Fork) -> Self {
+        Fork::ALL
+            .into_iter()
+            .filter(|earlier| *earlier <= fork)
+            // Any small value works, the block and the time of genesis are 0.
+            .fold(self, |request, fork| {
+                request.override_config(fork.config_key(), 1)
+            })
+    }
+
+    pub fn block(&self) -> BlockId {
+        self.block
+    }
+
+    /// The number of the block, when requested by number.
+    pub fn block_num(&self) -> Option<u64> {
+        match self.block {
+            BlockId::Number(num) => Some(num),
+            BlockId::Hash(_) => None,
+        }
+    }
+
+    /// Whether the trace is the native one, as stored in the trace cache: requested by number,
+    /// with legacy storage proofs and no override.
+    pub fn is_default(&self) -> bool {
+        self.block_num().is_some()
+            && self.storage_proof_format == StorageProofFormat::Legacy
+            && self.overrides.is_empty()
+    }
+
+    /// Params of `scroll_getBlockTraceByNumberOrHash`.
+    pub fn params(&self) -> Value {
+        let block = match self.block {
+            BlockId::Number(num) => format!("{num:#x}"),
+            BlockId::Hash(hash) => format!("{hash:?}"),
+        };
+        let mut config = json!({ "StorageProofFormat": self.storage_proof_format });
+        if !self.overrides.is_empty() {
+            config["overrides"] = json!(self.overrides);
+        }
+        json!([block, config])
+    }
+}
@@ -20,7 +20,7 @@ fn test_chunk_prove_verify() {
     );
 
     let trace_path = trace_path_for_test();
-    let traces = load_chunk(&trace_path).1;
+    let traces = load_chunk(&trace_path).unwrap().1;
     let chunk = ChunkProvingTask::new(traces);
     prove_and_verify_chunk(chunk, None, &params_map, ASSETS_DIR, &output_dir);
 }
@@ -169,7 +169,7 @@ fn gen_batch_proving_task(
 ) -> (BatchProvingTask, BatchHeader<MAX_AGG_SNARKS>) {
     let chunks: Vec<_> = chunk_dirs
         .iter()
-        .map(|chunk_dir| load_chunk(chunk_dir).1)
+        .map(|chunk_dir| load_chunk(chunk_dir).unwrap().1)
         .collect();
     let l1_message_popped = chunks
         .iter()
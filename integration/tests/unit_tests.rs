@@ -1,12 +1,41 @@
 // Fast tests which can be finished within minutes
 
 use integration::{
-    capacity_checker::{prepare_circuit_capacity_checker, run_circuit_capacity_checker, CCCMode},
-    test_util::{load_chunk_for_test, read_all},
+    batch_builder::{BatchBuilder, BatchLimit, BatchLimits},
+    capacity_checker::{
+        ccc_as_follower_full, ccc_as_follower_light, prepare_circuit_capacity_checker,
+        run_circuit_capacity_checker, scan_blocks, CCCMode, CccTolerance, ScanConfig, ScanError,
+        Severity,
+    },
+    ccc_report::{CccReport, Scope},
+    chunk_builder::{ChunkBuilder, ChunkLimit, ChunkLimits, ChunkStats},
+    chunk_info::chunk_info_from_block_traces,
+    constants::N_BLOB_BYTES,
+    fake_rollupscan::FakeRollupscan,
+    fork_impact::{ForkImpactRecord, ForkImpactReport},
+    l2geth::{self, ClientPolicy, ErrorKind, FetchConfig},
+    rollupscan,
+    simulation::Distribution,
+    test_util::{
+        copy_fixture_chunk, load_batch, load_batch_unchecked, load_chunk, load_chunk_for_test,
+        load_chunk_unchecked, load_fixture_chunk, read_all, read_dir_recursive, spawn_fake_l2geth,
+        FIXTURE_CHUNK_BLOCKS, FIXTURE_CHUNK_DIR,
+    },
+    trace_cache::TraceCache,
+    trace_file::{
+        block_num_of_file, convert_trace_file, decompress, read_block_trace, Compression,
+    },
+    trace_manifest::{TraceManifest, MANIFEST_FILE},
+    trace_request::{Fork, StorageProofFormat, TraceRequest},
 };
 use prover::{
-    calculate_row_usage_of_witness_block, chunk_trace_to_witness_block, init_env_and_log,
-    read_json, short_git_version,
+    calculate_row_usage_of_witness_block, chunk_trace_to_witness_block, eth_types::H256,
+    init_env_and_log, read_json, short_git_version, ChunkInfo, RowUsage,
+};
+use serde_json::json;
+use std::{
+    path::Path,
+    time::{Duration, Instant},
 };
 
 #[test]
@@ -97,7 +126,7 @@ fn test_evm_verifier_for_dumped_proof() {
 
 #[test]
 fn test_capacity_checker() {
-    init_env_and_log("integration");
+    let output_dir = init_env_and_log("integration");
     prepare_circuit_capacity_checker();
 
     let block_traces = load_chunk_for_test().1;
@@ -107,10 +136,449 @@ fn test_capacity_checker() {
     let ccc_modes = [
         CCCMode::Optimal,
         //CCCMode::Siger,
-        //CCCMode::FollowerLight,
+        CCCMode::FollowerLight,
         CCCMode::FollowerFull,
     ];
-    run_circuit_capacity_checker(batch_id, chunk_id, &block_traces, &ccc_modes);
+    let (report, discrepancies) = run_circuit_capacity_checker(
+        batch_id,
+        chunk_id,
+        &block_traces,
+        &ccc_modes,
+        &CccTolerance::default(),
+    );
+    assert!(
+        discrepancies.iter().all(|d| d.severity < Severity::Error),
+        "{discrepancies:#?}"
+    );
+
+    let chunk_records = report
+        .records()
+        .iter()
+        .filter(|r| r.scope == Scope::Chunk)
+        .collect::<Vec<_>>();
+    assert_eq!(chunk_records.len(), ccc_modes.len());
+    assert!(chunk_records
+        .iter()
+        .all(|r| r.tx_num > 0 && r.bottleneck_rows > 0));
+    report.save(format!("{output_dir}/ccc_report")).unwrap();
+}
+
+#[test]
+fn test_capacity_checker_follower_light() {
+    init_env_and_log("integration");
+    prepare_circuit_capacity_checker();
+
+    let block_traces = load_fixture_chunk();
+    let mut report = CccReport::new();
+    let (full, _) = ccc_as_follower_full(0, 0, &block_traces, &mut report);
+    let (light, _) = ccc_as_follower_light(0, 0, &block_traces, &mut report);
+
+    // Light mode skips the zktrie rows, so it never estimates more rows than the full mode,
+    // and estimates less in the sub-circuits of the zktrie.
+    let rows = |usage: &RowUsage| {
+        usage
+            .row_usage_details
+            .iter()
+            .map(|r| (r.name.clone(), r.row_number))
+            .collect::<std::collections::BTreeMap<_, _>>()
+    };
+    let (full_rows, light_rows) = (rows(&full), rows(&light));
+    for (name, light_row_number) in &light_rows {
+        assert!(
+            *light_row_number <= full_rows[name],
+            "{name}: light {light_row_number} > full {}",
+            full_rows[name]
+        );
+    }
+    assert_ne!(light_rows, full_rows);
+}
+
+#[test]
+fn test_capacity_checker_empty_block() {
+    init_env_and_log("integration");
+    prepare_circuit_capacity_checker();
+
+    // Averaging the CCC time of an empty block must not divide by zero.
+    let mut block_trace = load_fixture_chunk().swap_remove(0);
+    block_trace.transactions.clear();
+    block_trace.execution_results.clear();
+    block_trace.tx_storage_trace.clear();
+    let (report, _) = run_circuit_capacity_checker(
+        0,
+        0,
+        &[block_trace],
+        &[CCCMode::FollowerFull],
+        &CccTolerance::default(),
+    );
+    assert!(report.records().iter().all(|r| r.tx_num == 0));
+}
+
+#[test]
+fn test_ccc_tolerance() {
+    let tolerance: CccTolerance = "default=2, keccak=3, error_ratio=0.5".parse().unwrap();
+    assert_eq!(tolerance.rows_of("keccak"), 3);
+    assert_eq!(tolerance.rows_of("bytecode"), 1);
+    assert_eq!(tolerance.rows_of("evm"), 2);
+    assert_eq!(tolerance.error_ratio, 0.5);
+
+    assert!("keccak".parse::<CccTolerance>().is_err());
+    assert!("keccak=-1".parse::<CccTolerance>().is_err());
+}
+
+#[test]
+fn test_fork_impact() {
+    init_env_and_log("integration");
+    prepare_circuit_capacity_checker();
+
+    let trace = load_chunk_for_test().1.remove(0);
+    let block_num = trace.header.number.unwrap().as_u64();
+    let record = ForkImpactRecord::new(block_num, &trace, &trace);
+    assert_eq!(record.native_error, None);
+    assert_eq!(record.overridden_error, None);
+    assert!(!record.is_regression());
+    assert_eq!(record.gas_delta, 0);
+    assert!(!record.row_deltas.is_empty());
+    assert!(record.row_deltas.iter().all(|d| d.delta == 0));
+
+    let mut report = ForkImpactReport::default();
+    report.push(record);
+    let mut jsonl = vec![];
+    report.write_jsonl(&mut jsonl).unwrap();
+    assert_eq!(String::from_utf8(jsonl).unwrap().lines().count(), 1);
+    report.log_summary();
+}
+
+#[test]
+fn test_chunk_builder() {
+    init_env_and_log("integration");
+    prepare_circuit_capacity_checker();
+
+    let block_traces = load_fixture_chunk();
+    let build_chunks = |limits: ChunkLimits| {
+        let mut chunk_builder = ChunkBuilder::new(limits);
+        block_traces
+            .iter()
+            .filter_map(|trace| chunk_builder.add(trace.clone()).unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    let chunks = build_chunks(ChunkLimits {
+        max_blocks: Some(3),
+        ..ChunkLimits::unlimited()
+    });
+    assert_eq!(chunks.len(), block_traces.len() / 3);
+    for chunk in &chunks {
+        assert_eq!(chunk.blocks.len(), 3);
+        assert_eq!(chunk.stats.blocks, 3);
+        assert_eq!(chunk.closed_by, ChunkLimit::Blocks);
+    }
+
+    let max_txs = block_traces
+        .iter()
+        .map(|t| t.transactions.len())
+        .max()
+        .unwrap();
+    let chunks = build_chunks(ChunkLimits {
+        max_txs: Some(max_txs),
+        ..ChunkLimits::unlimited()
+    });
+    for chunk in &chunks {
+        assert!(chunk.stats.txs <= max_txs);
+        assert_eq!(chunk.closed_by, ChunkLimit::Txs);
+    }
+
+    // No limit is reached within a chunk, until the end of the blocks.
+    assert!(build_chunks(ChunkLimits::unlimited()).is_empty());
+    let mut chunk_builder = ChunkBuilder::new(ChunkLimits::unlimited());
+    for trace in &block_traces {
+        assert!(chunk_builder.add(trace.clone()).unwrap().is_none());
+    }
+    let chunk = chunk_builder.finish().unwrap();
+    assert_eq!(chunk.blocks.len(), block_traces.len());
+    assert_eq!(chunk.closed_by, ChunkLimit::Flush);
+    assert_eq!(
+        chunk.stats.first_block,
+        block_traces[0].header.number.unwrap().as_u64()
+    );
+    assert!(chunk_builder.finish().is_none());
+
+    // A block the capacity checker fails on is an error, not a panic.
+    let mut broken = block_traces
+        .iter()
+        .find(|t| !t.transactions.is_empty())
+        .unwrap()
+        .clone();
+    broken.execution_results.clear();
+    let mut chunk_builder = ChunkBuilder::new(ChunkLimits::unlimited());
+    assert!(chunk_builder.add(broken).is_err());
+    assert!(chunk_builder.finish().is_none());
+}
+
+#[test]
+fn test_chunk_limits_from_json() {
+    let output_dir = init_env_and_log("integration");
+
+    let path = format!("{output_dir}/chunk_policy.json");
+    std::fs::write(&path, r#"{"max_blocks": 5, "timeout_secs": null}"#).unwrap();
+    let limits = ChunkLimits::from_json_file(&path).unwrap();
+    assert_eq!(
+        limits,
+        ChunkLimits {
+            max_blocks: Some(5),
+            timeout_secs: None,
+            ..Default::default()
+        }
+    );
+    assert_eq!(ChunkLimits::default(), ChunkLimits::unlimited());
+
+    // The chunk proposer config of a rollup-relayer config file.
+    std::fs::write(
+        &path,
+        r#"{"l2_config": {"chunk_proposer_config": {
+            "propose_interval_milliseconds": 100,
+            "max_block_num_per_chunk": 100,
+            "max_tx_num_per_chunk": 100,
+            "max_l1_commit_gas_per_chunk": 5000000,
+            "max_l1_commit_calldata_size_per_chunk": 110000,
+            "chunk_timeout_sec": 300,
+            "max_row_consumption_per_chunk": 1000000
+        }}}"#,
+    )
+    .unwrap();
+    let limits = ChunkLimits::from_json_file(&path).unwrap();
+    assert_eq!(
+        limits,
+        ChunkLimits {
+            max_blocks: Some(100),
+            max_txs: Some(100),
+            max_payload_bytes: Some(110000),
+            max_rows: Some(1000000),
+            timeout_secs: Some(300),
+            ..ChunkLimits::unlimited()
+        }
+    );
+}
+
+#[test]
+fn test_batch_builder() {
+    init_env_and_log("integration");
+    prepare_circuit_capacity_checker();
+
+    let block_traces = load_fixture_chunk();
+    let mut chunk_builder = ChunkBuilder::new(ChunkLimits {
+        max_blocks: Some(1),
+        ..ChunkLimits::unlimited()
+    });
+    let mut batch_builder = BatchBuilder::new(BatchLimits {
+        max_chunks: 3,
+        skip_compression: true,
+        ..Default::default()
+    });
+    let mut batches = vec![];
+    for trace in &block_traces {
+        let chunk = chunk_builder.add(trace.clone()).unwrap().unwrap();
+        let witness_block = chunk_trace_to_witness_block(chunk.blocks).unwrap();
+        let chunk_info = ChunkInfo::from_witness_block(&witness_block, false);
+        batches.extend(batch_builder.add(chunk_info, &chunk.stats).unwrap());
+    }
+
+    assert_eq!(batches.len(), block_traces.len() / 3);
+    for batch in &batches {
+        assert_eq!(batch.chunks.len(), 3);
+        assert_eq!(batch.stats.chunks, 3);
+        assert_eq!(batch.stats.blocks, 3);
+        assert_eq!(batch.stats.last_block, batch.stats.first_block + 2);
+        assert_eq!(batch.closed_by, BatchLimit::Chunks);
+        assert!(batch.stats.blob_utilization() < 1.0);
+    }
+
+    // The chunks left at the end.
+    let rest = block_traces.len() % 3;
+    match batch_builder.finish() {
+        Some(batch) => {
+            assert_eq!(batch.chunks.len(), rest);
+            assert_eq!(batch.closed_by, BatchLimit::Flush);
+        }
+        None => assert_eq!(rest, 0),
+    }
+    assert!(batch_builder.finish().is_none());
+}
+
+#[test]
+fn test_batch_builder_oversize_chunk() {
+    init_env_and_log("integration");
+
+    let block_traces = load_fixture_chunk();
+    let witness_block = chunk_trace_to_witness_block(block_traces).unwrap();
+    let chunk = ChunkInfo::from_witness_block(&witness_block, false);
+    let mut oversize_chunk = chunk.clone();
+    oversize_chunk.tx_bytes = vec![1; N_BLOB_BYTES];
+    let chunk_stats = ChunkStats::default();
+
+    let mut batch_builder = BatchBuilder::new(BatchLimits {
+        max_chunks: 2,
+        skip_compression: true,
+        ..Default::default()
+    });
+    // Rejected alone, on an empty builder.
+    let err = batch_builder
+        .add(oversize_chunk.clone(), &chunk_stats)
+        .unwrap_err();
+    assert_eq!(err.limit, BatchLimit::BlobBytes);
+    assert!(err.blob_bytes > N_BLOB_BYTES);
+    assert!(err.uncompressed_bytes >= N_BLOB_BYTES);
+
+    // Rejected after a pending chunk, which is kept.
+    assert!(batch_builder
+        .add(chunk.clone(), &chunk_stats)
+        .unwrap()
+        .is_empty());
+    let err = batch_builder.add(oversize_chunk, &chunk_stats).unwrap_err();
+    assert_eq!(err.limit, BatchLimit::BlobBytes);
+    let batches = batch_builder.add(chunk, &chunk_stats).unwrap();
+    assert_eq!(batches.len(), 1);
+    let batch = &batches[0];
+    assert_eq!(batch.chunks.len(), 2);
+    assert_eq!(batch.closed_by, BatchLimit::Chunks);
+
+    // Every chunk exceeds a tiny blob.
+    let mut batch_builder = BatchBuilder::new(BatchLimits {
+        blob_utilization: 0.0,
+        skip_compression: true,
+        ..Default::default()
+    });
+    for _ in 0..2 {
+        let err = batch_builder
+            .add(batch.chunks[0].clone(), &chunk_stats)
+            .unwrap_err();
+        assert_eq!(err.limit, BatchLimit::BlobBytes);
+    }
+}
+
+#[test]
+fn test_batch_builder_late_chunk() {
+    init_env_and_log("integration");
+
+    let block_traces = load_fixture_chunk();
+    let witness_block = chunk_trace_to_witness_block(block_traces).unwrap();
+    let chunk = ChunkInfo::from_witness_block(&witness_block, false);
+    let chunk_stats = |first_timestamp, last_timestamp| ChunkStats {
+        blocks: 1,
+        first_timestamp,
+        last_timestamp,
+        ..Default::default()
+    };
+
+    let mut batch_builder = BatchBuilder::new(BatchLimits {
+        timeout_secs: Some(100),
+        skip_compression: true,
+        ..Default::default()
+    });
+    assert!(batch_builder
+        .add(chunk.clone(), &chunk_stats(0, 10))
+        .unwrap()
+        .is_empty());
+    // A chunk spanning more than the timeout alone closes the pending batch, then its own.
+    let batches = batch_builder
+        .add(chunk.clone(), &chunk_stats(20, 200))
+        .unwrap();
+    assert_eq!(batches.len(), 2);
+    assert_eq!(batches[0].chunks.len(), 1);
+    assert_eq!(batches[0].stats.last_timestamp, 10);
+    assert_eq!(batches[0].closed_by, BatchLimit::Timeout);
+    assert_eq!(batches[1].chunks.len(), 1);
+    assert_eq!(batches[1].stats.first_timestamp, 20);
+    assert_eq!(batches[1].closed_by, BatchLimit::Timeout);
+    assert!(batch_builder.finish().is_none());
+
+    // Alone on an empty builder, it's closed right away.
+    let batches = batch_builder.add(chunk, &chunk_stats(300, 500)).unwrap();
+    assert_eq!(batches.len(), 1);
+    assert_eq!(batches[0].closed_by, BatchLimit::Timeout);
+    assert!(batch_builder.finish().is_none());
+}
+
+#[test]
+fn test_batch_limits_from_json() {
+    let output_dir = init_env_and_log("integration");
+
+    let path = format!("{output_dir}/batch_policy.json");
+    std::fs::write(&path, r#"{"max_chunks": 10, "blob_utilization": 0.8}"#).unwrap();
+    let limits = BatchLimits::from_json_file(&path).unwrap();
+    assert_eq!(
+        limits,
+        BatchLimits {
+            max_chunks: 10,
+            blob_utilization: 0.8,
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn test_distribution() {
+    let mut distribution = Distribution::default();
+    assert_eq!(distribution.percentile(50.0), 0.0);
+
+    for value in (1..=10).rev() {
+        distribution.push(value as f64);
+    }
+    assert_eq!(distribution.len(), 10);
+    assert_eq!(distribution.percentile(0.0), 1.0);
+    assert_eq!(distribution.percentile(50.0), 5.0);
+    assert_eq!(distribution.percentile(90.0), 9.0);
+    assert_eq!(distribution.percentile(100.0), 10.0);
+    assert_eq!(distribution.mean(), 5.5);
+}
+
+#[test]
+fn test_chunk_info_from_block_traces() {
+    init_env_and_log("integration");
+
+    // Batches of a chunk sampled with gaps.
+    let sampled_batch_dirs = [
+        "tests/extra_traces/batch_300",
+        "tests/extra_traces/batch_495",
+        "tests/extra_traces/batch_73224",
+    ];
+    for batch_dir in read_dir_recursive("tests/extra_traces", "batch_").unwrap() {
+        let sampled = sampled_batch_dirs
+            .iter()
+            .any(|dir| Path::new(dir) == Path::new(&batch_dir));
+        let chunk_dirs = if sampled {
+            load_batch_unchecked(&batch_dir)
+        } else {
+            load_batch(&batch_dir)
+        };
+        for chunk_dir in chunk_dirs.unwrap_or_else(|e| panic!("{batch_dir}: {e:#}")) {
+            let block_traces = if sampled {
+                load_chunk_unchecked(&chunk_dir)
+            } else {
+                load_chunk(&chunk_dir)
+            }
+            .unwrap_or_else(|e| panic!("{chunk_dir}: {e:#}"))
+            .1;
+            let chunk_info = chunk_info_from_block_traces(&block_traces);
+
+            let witness_block = chunk_trace_to_witness_block(block_traces).unwrap();
+            let expected = ChunkInfo::from_witness_block(&witness_block, false);
+            assert_eq!(chunk_info.chain_id, expected.chain_id, "{chunk_dir}");
+            assert_eq!(
+                chunk_info.prev_state_root, expected.prev_state_root,
+                "{chunk_dir}"
+            );
+            assert_eq!(
+                chunk_info.post_state_root, expected.post_state_root,
+                "{chunk_dir}"
+            );
+            assert_eq!(
+                chunk_info.withdraw_root, expected.withdraw_root,
+                "{chunk_dir}"
+            );
+            assert_eq!(chunk_info.data_hash, expected.data_hash, "{chunk_dir}");
+            assert_eq!(chunk_info.tx_bytes, expected.tx_bytes, "{chunk_dir}");
+        }
+    }
 }
 
 #[test]
@@ -129,3 +597,466 @@ fn estimate_circuit_rows() {
         .clone();
     log::info!("final rows: {} {}", r.row_number, r.name);
 }
+
+#[test]
+fn test_trace_cache() {
+    let output_dir = init_env_and_log("integration");
+
+    let block_traces = load_fixture_chunk();
+    let cache_dir = format!("{output_dir}/trace_cache");
+    let _ = std::fs::remove_dir_all(&cache_dir);
+    let cache = TraceCache::new(&cache_dir).unwrap();
+    for trace in &block_traces {
+        let block_num = trace.header.number.unwrap().as_u64() as i64;
+        assert!(cache.get(block_num).unwrap().is_none());
+        cache.put(block_num, trace).unwrap();
+        let cached = cache.get(block_num).unwrap().unwrap();
+        assert_eq!(cached.header.hash, trace.header.hash);
+    }
+
+    let latest = block_traces.last().unwrap().header.number.unwrap().as_u64();
+    assert_eq!(cache.latest_block_number().unwrap(), Some(latest));
+
+    // Traces are checked against the index when the cache is reopened.
+    let first = block_traces[0].header.number.unwrap().as_u64() as i64;
+    let chain_id = block_traces[0].chain_id;
+    let cache = TraceCache::new(&cache_dir).unwrap().with_chain_id(chain_id);
+    assert!(cache.get(first).unwrap().is_some());
+    let other_chain = TraceCache::new(&cache_dir)
+        .unwrap()
+        .with_chain_id(chain_id + 1);
+    let err = other_chain.get(first).unwrap_err().to_string();
+    assert!(err.contains(&format!("is of chain {chain_id}")), "{err}");
+    assert!(other_chain.put(first, &block_traces[0]).is_err());
+
+    // A replaced trace, e.g. of a reorged block, is not served.
+    std::fs::copy(cache.path_of(first + 1), cache.path_of(first)).unwrap();
+    let err = cache.get(first).unwrap_err().to_string();
+    assert!(err.contains("changed since it was put"), "{err}");
+    let mut reorged = block_traces[0].clone();
+    reorged.header.hash = Some(H256::repeat_byte(0xab));
+    cache.put(first, &reorged).unwrap();
+    let err = cache.get(first).unwrap_err().to_string();
+    assert!(err.contains("doesn't link"), "{err}");
+}
+
+#[test]
+fn test_compressed_traces() {
+    let output_dir = init_env_and_log("integration");
+
+    let json = read_all(format!("{FIXTURE_CHUNK_DIR}/block_1341.json"));
+    for compression in Compression::ALL {
+        let compressed = compression.compress(&json).unwrap();
+        assert_eq!(Compression::of_bytes(&compressed), compression);
+        assert_eq!(&*decompress(&compressed).unwrap(), &json[..]);
+    }
+    assert_eq!(
+        Compression::of_path(Path::new("block_1.json.zst")),
+        Some(Compression::Zstd)
+    );
+    assert_eq!(Compression::of_path(Path::new("block_1.json.tmp")), None);
+    assert_eq!(block_num_of_file(Path::new("a/block_1.json.gz")), Some(1));
+
+    // A chunk dir of mixed compressions, converted in place.
+    let chunk_dir = format!("{output_dir}/compressed_chunk");
+    let copies = copy_fixture_chunk(&chunk_dir, 1341..=1343);
+    for (path, compression) in copies.iter().zip(Compression::ALL) {
+        convert_trace_file(path, compression).unwrap();
+    }
+    let (paths, traces) = load_chunk(&chunk_dir).unwrap();
+    assert!(paths[1].ends_with("block_1342.json.gz"), "{paths:?}");
+    assert!(paths[2].ends_with("block_1343.json.zst"), "{paths:?}");
+    assert_eq!(traces.len(), 3);
+
+    let cache_dir = format!("{output_dir}/compressed_trace_cache");
+    let _ = std::fs::remove_dir_all(&cache_dir);
+    let cache = TraceCache::new(&cache_dir)
+        .unwrap()
+        .with_compression(Compression::Zstd);
+    for trace in &traces {
+        let block_num = trace.header.number.unwrap().as_u64() as i64;
+        cache.put(block_num, trace).unwrap();
+        assert!(cache.path_of(block_num).exists());
+        let cached = cache.get(block_num).unwrap().unwrap();
+        assert_eq!(cached.header.hash, trace.header.hash);
+    }
+    assert_eq!(cache.block_range().unwrap(), Some((1341, 1343)));
+
+    // Traces of any compression are read.
+    let cache = TraceCache::new(&cache_dir).unwrap();
+    assert!(cache.get(1342).unwrap().is_some());
+}
+
+#[test]
+fn test_load_chunk_errors() {
+    let output_dir = init_env_and_log("integration");
+
+    let chunk_dir = format!("{output_dir}/load_chunk");
+    copy_fixture_chunk(&chunk_dir, [1341, 1342, 1344]);
+    let err = load_chunk(&chunk_dir).unwrap_err().to_string();
+    assert!(
+        err.contains("block 1344 (") && err.contains("is not contiguous with block 1342"),
+        "{err}"
+    );
+    // Unless the dir is loaded as a sample.
+    assert_eq!(load_chunk_unchecked(&chunk_dir).unwrap().1.len(), 3);
+
+    std::fs::copy(
+        format!("{FIXTURE_CHUNK_DIR}/block_1341.json"),
+        format!("{chunk_dir}/block_1345.json"),
+    )
+    .unwrap();
+    let err = load_chunk_unchecked(&chunk_dir).unwrap_err().to_string();
+    assert!(
+        err.contains("is the trace of block 1341, expected 1345"),
+        "{err}"
+    );
+
+    std::fs::remove_file(format!("{chunk_dir}/block_1344.json")).unwrap();
+    std::fs::remove_file(format!("{chunk_dir}/block_1345.json")).unwrap();
+    assert_eq!(load_chunk(&chunk_dir).unwrap().1.len(), 2);
+
+    // A trace which doesn't link to the previous block, e.g. of a reorged block.
+    let mut trace = read_block_trace(format!("{chunk_dir}/block_1342.json")).unwrap();
+    trace.header.parent_hash = H256::repeat_byte(0xab);
+    std::fs::write(
+        format!("{chunk_dir}/block_1342.json"),
+        serde_json::to_vec(&trace).unwrap(),
+    )
+    .unwrap();
+    let err = load_chunk(&chunk_dir).unwrap_err().to_string();
+    assert!(
+        err.contains("parent hash of block 1342 (")
+            && err.contains("is not the hash of block 1341"),
+        "{err}"
+    );
+    copy_fixture_chunk(&chunk_dir, [1341, 1342]);
+
+    // Chunks of a batch follow each other.
+    let batch_dir = format!("{output_dir}/load_batch");
+    copy_fixture_chunk(format!("{batch_dir}/chunk_1"), 1341..=1342);
+    copy_fixture_chunk(format!("{batch_dir}/chunk_2"), 1343..=1345);
+    assert_eq!(load_batch(&batch_dir).unwrap().len(), 2);
+    copy_fixture_chunk(format!("{batch_dir}/chunk_2"), 1344..=1345);
+    let err = format!("{:#}", load_batch(&batch_dir).unwrap_err());
+    assert!(
+        err.contains("chunk_2 doesn't follow the previous chunk"),
+        "{err}"
+    );
+    assert_eq!(load_batch_unchecked(&batch_dir).unwrap().len(), 2);
+
+    std::fs::write(format!("{chunk_dir}/notes.json"), "{}").unwrap();
+    let err = load_chunk(&chunk_dir).unwrap_err().to_string();
+    assert!(err.contains("notes.json"), "{err}");
+
+    std::fs::remove_file(format!("{chunk_dir}/notes.json")).unwrap();
+    std::fs::write(format!("{chunk_dir}/block_1343.json"), "").unwrap();
+    let err = format!("{:#}", load_chunk(&chunk_dir).unwrap_err());
+    assert!(err.contains("block_1343.json"), "{err}");
+}
+
+#[test]
+fn test_trace_manifest() {
+    let output_dir = init_env_and_log("integration");
+
+    let corpus_dir = format!("{output_dir}/trace_corpus");
+    let _ = std::fs::remove_dir_all(&corpus_dir);
+    let chunk_dir = format!("{corpus_dir}/batch_25/chunk_112");
+    copy_fixture_chunk(&chunk_dir, FIXTURE_CHUNK_BLOCKS);
+
+    let manifest = TraceManifest::generate(&corpus_dir).unwrap();
+    assert_eq!(manifest.entries.len(), FIXTURE_CHUNK_BLOCKS.count());
+    let first = &manifest.entries[0];
+    assert_eq!(first.path, "batch_25/chunk_112/block_1341.json");
+    assert_eq!(first.block_num, 1341);
+    assert_eq!(first.chain_id, 534352);
+    assert_eq!(first.fork, None);
+    assert!(manifest
+        .entries
+        .windows(2)
+        .all(|w| w[1].parent_hash == w[0].block_hash));
+
+    let manifest_path = format!("{corpus_dir}/{MANIFEST_FILE}");
+    manifest.save(&manifest_path).unwrap();
+    let manifest = TraceManifest::load(&manifest_path).unwrap();
+    manifest.validate(&corpus_dir).unwrap();
+    manifest.check_linkage().unwrap();
+    let mut updated = TraceManifest::default();
+    updated.update(&corpus_dir, &chunk_dir).unwrap();
+    assert_eq!(updated, manifest);
+
+    std::fs::remove_file(format!("{chunk_dir}/block_1345.json")).unwrap();
+    let sampled = TraceManifest::generate(&corpus_dir).unwrap();
+    let err = sampled.check_linkage().unwrap_err().to_string();
+    assert!(
+        err.contains("batch_25/chunk_112/block_1346.json is not contiguous with block 1344"),
+        "{err}"
+    );
+
+    std::fs::write(format!("{chunk_dir}/block_1350.json"), "{}").unwrap();
+    std::fs::write(format!("{chunk_dir}/block_1351.json"), "{}").unwrap();
+    let err = manifest.validate(&corpus_dir).unwrap_err().to_string();
+    assert!(err.contains("block_1350.json has sha256"), "{err}");
+    assert!(
+        err.contains("block_1351.json is not in the manifest"),
+        "{err}"
+    );
+
+    assert_eq!(
+        Fork::of_block(534352, 7_096_836, 1_720_000_000),
+        Some(Fork::Curie)
+    );
+    assert_eq!(
+        Fork::of_block(534352, 8_000_000, 1_724_227_200),
+        Some(Fork::Darwin)
+    );
+    assert_eq!(Fork::of_block(1, 8_000_000, 1_724_227_200), None);
+}
+
+#[test]
+fn test_trace_request() {
+    let request = TraceRequest::by_number(0x485490);
+    assert!(request.is_default());
+    assert_eq!(
+        request.params(),
+        json!(["0x485490", {"StorageProofFormat": "legacy"}])
+    );
+
+    let request = TraceRequest::by_number(0x485490)
+        .storage_proof_format(StorageProofFormat::Flatten)
+        .enable_fork("curie".parse::<Fork>().unwrap())
+        .override_config("darwinTime", 1_700_000_000);
+    assert!(!request.is_default());
+    assert_eq!(
+        request.params(),
+        json!(["0x485490", {
+            "StorageProofFormat": "flatten",
+            "overrides": {"bernoulliBlock": 1, "curieBlock": 1, "darwinTime": 1_700_000_000}
+        }])
+    );
+    let request = TraceRequest::by_number(0x485490).enable_fork(Fork::DarwinV2);
+    assert_eq!(
+        request.params()[1]["overrides"],
+        json!({"bernoulliBlock": 1, "curieBlock": 1, "darwinTime": 1, "darwinv2Time": 1})
+    );
+
+    let request = TraceRequest::by_hash(H256::repeat_byte(0xab));
+    assert!(!request.is_default());
+    assert_eq!(request.params()[0], json!(format!("0x{}", "ab".repeat(32))));
+}
+
+#[tokio::test]
+async fn test_fake_l2geth() {
+    init_env_and_log("integration");
+
+    let block_traces = load_fixture_chunk();
+    let url = spawn_fake_l2geth(FIXTURE_CHUNK_DIR).await;
+    let client = l2geth::Client::new("test_fake_l2geth", &url).unwrap();
+
+    let latest = block_traces.last().unwrap().header.number.unwrap().as_u64();
+    assert_eq!(client.get_block_number().await.unwrap(), latest);
+    for trace in &block_traces {
+        let block_num = trace.header.number.unwrap().as_u64() as i64;
+        let fetched = client.get_block_trace_by_num(block_num).await.unwrap();
+        assert_eq!(fetched.header.hash, trace.header.hash);
+        assert_eq!(fetched.transactions.len(), trace.transactions.len());
+    }
+    assert!(client
+        .get_block_trace_by_num(latest as i64 + 1)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_client_policy() {
+    init_env_and_log("integration");
+
+    let url = spawn_fake_l2geth(FIXTURE_CHUNK_DIR).await;
+    let unknown_block = i64::MAX / 2;
+
+    // An unknown block is a fatal error by default.
+    let client = l2geth::Client::new("test_client_policy", &url).unwrap();
+    let err = client
+        .get_block_trace_by_num(unknown_block)
+        .await
+        .unwrap_err();
+    assert_eq!(ClientPolicy::default().classify(&err), ErrorKind::Fatal);
+
+    // Retried when its error code is retriable, the requests spaced by the rate limit.
+    let policy = ClientPolicy {
+        max_retries: 2,
+        initial_backoff: Duration::from_millis(10),
+        max_requests_per_sec: Some(20.0),
+        retriable_codes: vec![-32000],
+        ..Default::default()
+    };
+    assert_eq!(policy.classify(&err), ErrorKind::Retriable);
+    let client = l2geth::Client::new("test_client_policy", &url)
+        .unwrap()
+        .with_policy(policy);
+    let start = Instant::now();
+    assert!(client.get_block_trace_by_num(unknown_block).await.is_err());
+    // 3 requests, 50ms apart.
+    assert!(start.elapsed() >= Duration::from_millis(100));
+}
+
+#[tokio::test]
+async fn test_fetch_range() {
+    init_env_and_log("integration");
+
+    let block_traces = load_fixture_chunk();
+    let url = spawn_fake_l2geth(FIXTURE_CHUNK_DIR).await;
+    let client = l2geth::Client::new("test_fetch_range", &url).unwrap();
+
+    let first = block_traces[0].header.number.unwrap().as_u64() as i64;
+    let latest = first + block_traces.len() as i64 - 1;
+    let config = FetchConfig {
+        concurrency: 3,
+        prefetch: 2,
+    };
+    // The block after the latest one is unknown to l2geth, which is not retried.
+    let mut traces = client.fetch_range(first, latest + 1, &config);
+    for trace in &block_traces {
+        let (block_num, fetched) = traces.next().await.unwrap();
+        assert_eq!(
+            Some(block_num as u64),
+            trace.header.number.map(|n| n.as_u64())
+        );
+        assert_eq!(fetched.unwrap().header.hash, trace.header.hash);
+    }
+    let (block_num, fetched) = traces.next().await.unwrap();
+    assert_eq!(block_num, latest + 1);
+    assert!(fetched.is_err());
+    assert!(traces.next().await.is_none());
+}
+
+#[tokio::test]
+async fn test_scan_blocks() {
+    init_env_and_log("integration");
+    prepare_circuit_capacity_checker();
+
+    let block_traces = load_fixture_chunk();
+    let first = block_traces
+        .first()
+        .unwrap()
+        .header
+        .number
+        .unwrap()
+        .as_u64();
+    let last = block_traces.last().unwrap().header.number.unwrap().as_u64();
+    let config = ScanConfig {
+        fetch_concurrency: 4,
+        workers: 3,
+        queue_size: 2,
+        modes: vec![CCCMode::Optimal],
+        tolerance: CccTolerance::default(),
+    };
+
+    // The block before the chunk is missing.
+    let mut block_nums = vec![];
+    let stats = scan_blocks(
+        first - 1,
+        last,
+        &config,
+        |block_num| {
+            let trace = block_traces
+                .iter()
+                .find(|t| t.header.number.unwrap().as_u64() == block_num)
+                .cloned();
+            async move { trace.ok_or_else(|| anyhow::anyhow!("block {block_num} not found")) }
+        },
+        |block_num, result| {
+            match result {
+                Ok(result) => {
+                    assert_eq!(result.block_num, block_num);
+                    assert_eq!(result.report.records().len(), 1);
+                }
+                Err(e) => {
+                    assert_eq!(block_num, first - 1);
+                    assert!(matches!(e, ScanError::Fetch(_)));
+                }
+            }
+            block_nums.push(block_num);
+        },
+    )
+    .await;
+
+    assert_eq!(block_nums, (first - 1..=last).collect::<Vec<_>>());
+    assert_eq!(stats.blocks, last - first + 2);
+    assert_eq!(stats.failed, 1);
+    assert_eq!(
+        stats.txs,
+        block_traces
+            .iter()
+            .map(|t| t.transactions.len() as u64)
+            .sum::<u64>()
+    );
+}
+
+#[tokio::test]
+async fn test_fake_rollupscan() {
+    init_env_and_log("integration");
+
+    let addr = FakeRollupscan::from_fixture_dir("tests/test_data/rollupscan")
+        .unwrap()
+        .spawn("127.0.0.1:0")
+        .await
+        .unwrap();
+    let client = rollupscan::Client::new("test_fake_rollupscan", &format!("http://{addr}/api"));
+
+    let chunks = client
+        .get_chunk_info_by_batch_index(25)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[0].start_block_number, 1341);
+    assert_eq!(chunks[1].end_block_number, 1370);
+    assert!(client
+        .get_chunk_info_by_batch_index(26)
+        .await
+        .unwrap()
+        .is_none());
+
+    let batch = client.get_latest_batch_info().await.unwrap();
+    assert_eq!(batch.index, 25);
+    assert!(batch.is_finalized());
+    assert!(client.get_batch_info_by_index(26).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_fake_rollupscan_indexes() {
+    let output_dir = init_env_and_log("fake_rollupscan_indexes");
+
+    // A finalized batch 25, followed by a committed batch 26 and a pending batch 27.
+    let fixture_dir = format!("{output_dir}/rollupscan");
+    std::fs::create_dir_all(&fixture_dir).unwrap();
+    let fixture: serde_json::Value = read_json("tests/test_data/rollupscan/batch_25.json").unwrap();
+    for (index, status) in [(25, "finalized"), (26, "committed"), (27, "precommitted")] {
+        let mut fixture = fixture.clone();
+        fixture["batch"]["index"] = json!(index);
+        fixture["batch"]["rollup_status"] = json!(status);
+        std::fs::write(
+            format!("{fixture_dir}/batch_{index}.json"),
+            serde_json::to_vec(&fixture).unwrap(),
+        )
+        .unwrap();
+    }
+
+    let addr = FakeRollupscan::from_fixture_dir(&fixture_dir)
+        .unwrap()
+        .spawn("127.0.0.1:0")
+        .await
+        .unwrap();
+    let client = rollupscan::Client::new("test_fake_rollupscan", &format!("http://{addr}/api"));
+
+    let indexes = client.get_last_batch_indexes().await.unwrap();
+    assert_eq!(indexes.all_index, 27);
+    assert_eq!(indexes.committed_index, 26);
+    assert_eq!(indexes.finalized_index, 25);
+
+    let batch = client.get_latest_batch_info().await.unwrap();
+    assert_eq!(batch.index, 26);
+    assert!(batch.is_committed());
+    assert!(!batch.is_finalized());
+}